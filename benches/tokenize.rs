@@ -0,0 +1,65 @@
+// 大きな生成ソースに対する字句解析のスループットを測る
+// cargo bench --bench tokenize
+use std::time::{Duration, Instant};
+
+use rustcc::diagnostic::SourceMap;
+use rustcc::token::{tokenize, Lexer};
+
+const TARGET_SIZE: usize = 8 * 1024 * 1024;
+const ITERATIONS: u32 = 5;
+
+// 関数定義を繰り返して指定サイズ程度のソースを作る
+fn generate() -> String {
+    let mut src = String::with_capacity(TARGET_SIZE + 1024);
+    let mut i = 0;
+    while src.len() < TARGET_SIZE {
+        src.push_str(&format!(
+            "// function {i}\n\
+             func_{i}(a, b, c) {{\n\
+             \x20   /* locals */\n\
+             \x20   x_{i} = a * 0x{i:x} + (b << 2) - c / 7;\n\
+             \x20   if (x_{i} >= {i} && b != 0) {{ x_{i} += 'a'; }} else {{ x_{i} -= 1; }}\n\
+             \x20   for (k = 0; k < 10; k++) y = y ^ k | (x_{i} & 0xff);\n\
+             \x20   s = \"string literal {i}\\n\";\n\
+             \x20   return x_{i} % 3;\n\
+             }}\n\n",
+            i = i
+        ));
+        i += 1;
+    }
+    src
+}
+
+fn report(name: &str, bytes: usize, tokens: usize, elapsed: Duration) {
+    let secs = elapsed.as_secs_f64() / ITERATIONS as f64;
+    println!(
+        "{:<10} {:>8.2} ms/iter {:>9.1} MB/s {:>7.1} Mtok/s",
+        name,
+        secs * 1000.0,
+        bytes as f64 / secs / 1e6,
+        tokens as f64 / secs / 1e6
+    );
+}
+
+fn main() {
+    let mut sources = SourceMap::new();
+    let id = sources.add("bench.c".into(), generate());
+    let file = sources.get(id);
+    let bytes = file.src.len();
+    println!("input: {:.1} MB", bytes as f64 / 1e6);
+
+    // 全トークンをVecに集める
+    let mut count = 0;
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        count = tokenize(file).expect("bench input must tokenize").len();
+    }
+    report("tokenize", bytes, count, start.elapsed());
+
+    // イテレータとして流すだけ(Vecを作らない)
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        count = Lexer::new(file).count();
+    }
+    report("lexer", bytes, count, start.elapsed());
+}
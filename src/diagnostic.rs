@@ -0,0 +1,116 @@
+use std::fmt;
+
+use crate::Span;
+
+// 診断コード
+pub const E_UNKNOWN_CHAR: &str = "E0001";     // 未対応の文字
+pub const E_UNTERMINATED: &str = "E0002";     // 閉じられていないコメントなど
+pub const E_BAD_ESCAPE: &str = "E0003";       // 不正な文字定数・エスケープ
+pub const E_BAD_NUMBER: &str = "E0004";       // 不正な整数リテラル
+pub const E_UNEXPECTED_TOKEN: &str = "E0100"; // 構文エラー
+pub const E_UNDECLARED: &str = "E0101";       // 未宣言の識別子
+pub const E_REDEFINITION: &str = "E0102";     // 同じスコープでの再定義
+pub const E_UNSUPPORTED: &str = "E0200";      // 未対応の構文
+
+#[derive(Debug, Clone)]
+pub struct SourceFile {
+    pub id: usize,
+    pub name: String,
+    pub src: String,
+}
+
+impl SourceFile {
+    // 指定した行(1始まり)の内容を改行抜きで返す
+    pub fn line(&self, line: usize) -> &str {
+        self.src
+            .split('\n')
+            .nth(line.saturating_sub(1))
+            .map(|l| l.trim_end_matches('\r'))
+            .unwrap_or("")
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct SourceMap {
+    files: Vec<SourceFile>,
+}
+
+impl SourceMap {
+    pub fn new() -> Self {
+        SourceMap { files: vec![] }
+    }
+
+    pub fn add(&mut self, name: String, src: String) -> usize {
+        let id = self.files.len();
+        self.files.push(SourceFile { id, name, src });
+        id
+    }
+
+    pub fn get(&self, id: usize) -> &SourceFile {
+        &self.files[id]
+    }
+}
+
+// 診断はすべてコンパイルを止めるエラーとしてErrで返す
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Severity {
+    Error,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = match self {
+            Severity::Error => "error",
+        };
+        write!(f, "{}", s)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub code: &'static str,
+    pub message: String,
+    pub span: Span,
+}
+
+impl Diagnostic {
+    pub fn new(severity: Severity, code: &'static str, span: Span, message: String) -> Self {
+        Diagnostic { severity, code, message, span }
+    }
+
+    pub fn error(code: &'static str, span: Span, message: String) -> Self {
+        Self::new(Severity::Error, code, span, message)
+    }
+
+    // file.c:3:14: error[E0100]: expected ';'
+    //     3 |     return 5
+    //       |             ^
+    pub fn render(&self, sources: &SourceMap) -> String {
+        let file = sources.get(self.span.file);
+        let line = file.line(self.span.line);
+        let gutter = self.span.line.to_string();
+
+        // タブはそのまま残してキャレットの位置を合わせる
+        let mut caret: String = line
+            .chars()
+            .take(self.span.col.saturating_sub(1))
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+        caret.push('^');
+        // 行末を越えないように下線を引く
+        let rest = line.chars().count().saturating_sub(self.span.col.saturating_sub(1));
+        let width = self.span.len.min(rest).max(1);
+        for _ in 1..width {
+            caret.push('~');
+        }
+
+        let mut s = format!(
+            "{}:{}:{}: {}[{}]: {}\n",
+            file.name, self.span.line, self.span.col, self.severity, self.code, self.message
+        );
+        s.push_str(&format!(" {} | {}\n", gutter, line));
+        s.push_str(&format!(" {} | {}", " ".repeat(gutter.len()), caret));
+        s
+    }
+}
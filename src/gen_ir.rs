@@ -1,22 +1,34 @@
-use crate::parse::NodeType;
+use crate::Span;
+use crate::symbol::Symbol;
+use crate::types::Type;
+use crate::parse::{Init, Node, NodeType};
+use crate::diagnostic::{Diagnostic, E_UNSUPPORTED};
 
-pub fn gen_ir(nodes: Vec<NodeType>) -> Vec<Function> {
+pub fn gen_ir(nodes: Vec<Node>) -> Result<Program, Vec<Diagnostic>> {
+    // ラベルの番号はファイル全体で一意にする
+    let mut generator = IrGenerator::new();
     let mut funcs = vec![];
+    let mut globals = vec![];
     for node in nodes {
-        match node {
-            NodeType::Func(name, args, body, stacksize) => {
-                let mut generator = IrGenerator::new(vec![]);
+        match node.kind {
+            NodeType::GVarDef(name, init, is_static) => {
+                let data = generator.global_data(&node.ty, init);
+                globals.push(Global { name, size: node.ty.size(), align: node.ty.align(), is_static, data });
+            },
+            NodeType::Func(name, args, body, stacksize, is_static) => {
+                generator.num_regs = 0;
 
+                // レジスタで渡された引数をスタック上のローカル変数に退避する
                 for (i, arg) in args.iter().enumerate() {
-                    if let NodeType::LVar(offset) = arg {
-                        generator.store_arg(Some((*offset) as usize), Some(i));
+                    if let NodeType::LVar(offset) = arg.kind {
+                        generator.store_arg(Some(offset as usize), Some(i), arg.ty.size() as u8);
                     } else {
                         unreachable!();
                     }
                 }
 
                 let stmts;
-                if let NodeType::CompStmt(_stmts) = *body {
+                if let NodeType::CompStmt(_stmts) = body.kind {
                     stmts = _stmts;
                 } else {
                     unreachable!();
@@ -24,28 +36,62 @@ pub fn gen_ir(nodes: Vec<NodeType>) -> Vec<Function> {
                 for stmt in stmts {
                     generator.gen_stmt(stmt);
                 }
-                
-                funcs.push(Function::new(name,  generator.code.clone(), stacksize));
+
+                let code = std::mem::take(&mut generator.code);
+                funcs.push(Function::new(name, node.span, code, stacksize, is_static));
             },
-            _ => panic!("supported is function only!")
+            _ => {
+                let message = "supported is function only!".to_string();
+                generator.errors.push(Diagnostic::error(E_UNSUPPORTED, node.span, message));
+            }
         }
     }
-    funcs
+    if !generator.errors.is_empty() {
+        return Err(generator.errors);
+    }
+    Ok(Program { funcs, globals, strings: generator.strings })
+}
+
+#[derive(Clone, Debug)]
+pub struct Program {
+    pub funcs: Vec<Function>,
+    pub globals: Vec<Global>,
+    pub strings: Vec<Vec<u8>>,  // 文字列リテラル(.LC{n})
+}
+
+#[derive(Clone, Debug)]
+pub struct Global {
+    pub name: Symbol,
+    pub size: usize,
+    pub align: usize,
+    pub is_static: bool,
+    pub data: GlobalData,
+}
+
+#[derive(Clone, Debug)]
+pub enum GlobalData {
+    Zero,               // .bss
+    Bytes(Vec<u8>),     // .data
+    Label(String),      // 文字列リテラルなどのアドレス
 }
 
 #[derive(Clone, Debug)]
 pub struct Function {
-    pub name: String,
+    pub name: Symbol,
+    pub span: Span,
     pub code: Vec<IR>,
     pub stacksize: usize,
+    pub is_static: bool,
 }
 
 impl Function {
-    fn new(name: String, code: Vec<IR>, stacksize: usize) -> Self {
+    fn new(name: Symbol, span: Span, code: Vec<IR>, stacksize: usize, is_static: bool) -> Self {
         Function {
             name,
+            span,
             code,
             stacksize,
+            is_static,
         }
     }
 }
@@ -56,20 +102,41 @@ pub enum IROp {
     Sub,
     Mul,
     Div,
+    UDiv,
+    Mod,
+    UMod,
+    And,
+    Or,
+    Xor,
+    Shl,
+    Shr,    // 論理右シフト
+    Sar,    // 算術右シフト
+    Not,
     Eq,
     Ne,
     Le,
     Lt,
+    ULe,
+    ULt,
     Imm,
+    LabelAddr(String),
+    GlobalAddr(Symbol),
     Return,
-    Call(String, usize),
+    Push,
+    Call(Symbol, usize),    // 関数名, Pushした引数の数
     Label,
     Bprel,
     Jmp,
     Unless,
-    Load(u8),
+    Load(u8, bool),     // 大きさ, 符号無しならゼロ拡張
     Store(u8),
     StoreArg(u8),
+    Memcpy(usize),
+    Mov,
+    Cast(u8, bool),     // 下位の大きさ分だけ残して拡張する
+    Spill,              // lhsをrbp-rhsに書き出す
+    Reload,             // rbp-rhsからlhsに読み戻す
+    Kill,
 }
 
 #[derive(Clone, Debug)]
@@ -83,17 +150,41 @@ impl IR {
     fn new(op: IROp, lhs: Option<usize>, rhs: Option<usize>) -> Self {
         IR { op, lhs, rhs }
     }
+
+    // lhs, rhsのうち仮想レジスタを指しているもの
+    pub fn regs(&self) -> (bool, bool) {
+        use self::IROp::*;
+        match self.op {
+            Label | Jmp | StoreArg(_) => (false, false),
+            Imm | LabelAddr(_) | GlobalAddr(_) | Bprel | Return | Unless | Push | Call(..) | Cast(..) | Not
+            | Spill | Reload | Kill => (true, false),
+            _ => (true, true),
+        }
+    }
+}
+
+fn is_unsigned_cmp(node: &Node) -> bool {
+    node.ty.is_unsigned() || node.ty.base().is_some()
 }
 
 struct IrGenerator {
     code: Vec<IR>,
     num_regs: usize,
     nlabel: usize,
+    strings: Vec<Vec<u8>>,
+    errors: Vec<Diagnostic>,
 }
 
 impl IrGenerator {
-    fn new(code: Vec<IR>) -> Self {
-        Self { code, num_regs: 0, nlabel: 0 }
+    fn new() -> Self {
+        Self { code: vec![], num_regs: 0, nlabel: 0, strings: vec![], errors: vec![] }
+    }
+
+    // 未対応のノードはエラーとして記録し、生成は続ける
+    fn unsupported(&mut self, node: &Node, context: &str) -> Option<usize> {
+        let message = format!("unknown node in {}: {}", context, node.kind.name());
+        self.errors.push(Diagnostic::error(E_UNSUPPORTED, node.span, message));
+        None
     }
 
     fn add(&mut self, op: IROp, lhs: Option<usize>, rhs: Option<usize>) {
@@ -108,73 +199,283 @@ impl IrGenerator {
         self.add(IROp::Jmp, x, None);
     }
 
-    fn load(&mut self, dst: Option<usize>, src: Option<usize>) {
-        self.add(IROp::Load(8), dst, src);
+    // 読み書きする幅は型の大きさに合わせる
+    // レジスタ上の値は常に型に合わせて64ビットに拡張しておく
+    fn load(&mut self, dst: Option<usize>, src: Option<usize>, ty: &Type) {
+        self.add(IROp::Load(ty.size() as u8, ty.is_unsigned()), dst, src);
+    }
+
+    fn store(&mut self, dst: Option<usize>, src: Option<usize>, size: usize) {
+        self.add(IROp::Store(size as u8), dst, src);
     }
 
-    fn store(&mut self, dst: Option<usize>, src: Option<usize>) {
-        self.add(IROp::Store(8), dst, src);
+    fn kill(&mut self, r: Option<usize>) {
+        self.add(IROp::Kill, r, None);
     }
 
-    fn store_arg(&mut self, bpoff: Option<usize>, argreg: Option<usize>) {
-        self.add(IROp::StoreArg(8), bpoff, argreg);
+    fn store_arg(&mut self, bpoff: Option<usize>, argreg: Option<usize>, size: u8) {
+        self.add(IROp::StoreArg(size), bpoff, argreg);
+    }
+
+    fn string_label(&mut self, data: Vec<u8>) -> String {
+        let label = format!(".LC{}", self.strings.len());
+        self.strings.push(data);
+        label
+    }
+
+    // 整数はリトルエンディアンで型の大きさ分だけ並べる
+    fn global_data(&mut self, ty: &Type, init: Init) -> GlobalData {
+        match init {
+            Init::Zero => GlobalData::Zero,
+            Init::Int(val) => GlobalData::Bytes(val.to_le_bytes()[..ty.size()].to_vec()),
+            Init::Str(mut data) => match ty {
+                Type::Array(..) => {
+                    data.resize(ty.size(), 0);
+                    GlobalData::Bytes(data)
+                },
+                _ => GlobalData::Label(self.string_label(data)),
+            },
+        }
     }
 
-    fn gen_lval(&mut self, node: Box<NodeType>) -> Option<usize> {
-        match *node {
+    fn gen_lval(&mut self, node: Node) -> Option<usize> {
+        match node.kind {
             NodeType::LVar(offset) => {
                 let r = Some(self.num_regs);
                 self.num_regs += 1;
                 self.add(IROp::Bprel, r, Some(offset as usize));
                 r
             },
-            _ => unreachable!(),
+            NodeType::GVar(name) => {
+                let r = Some(self.num_regs);
+                self.num_regs += 1;
+                self.add(IROp::GlobalAddr(name), r, None);
+                r
+            },
+            // *pのアドレスはpの値
+            NodeType::Deref(expr) => self.gen_expr(*expr),
+            NodeType::Member(expr, offset) => {
+                let r = self.gen_lval(*expr);
+                let off = Some(self.num_regs);
+                self.num_regs += 1;
+                self.add(IROp::Imm, off, Some(offset));
+                self.add(IROp::Add, r, off);
+                self.kill(off);
+                r
+            },
+            _ => {
+                let message = "lvalue required".to_string();
+                self.errors.push(Diagnostic::error(E_UNSUPPORTED, node.span, message));
+                None
+            },
         }
     }
 
-    fn gen_binop(&mut self, op: IROp, lhs: Box<NodeType>, rhs: Box<NodeType>) -> Option<usize> {
-        let r1 = self.gen_expr(*lhs);
-        let r2 = self.gen_expr(*rhs);
+    fn gen_binop(&mut self, op: IROp, lhs: Node, rhs: Node) -> Option<usize> {
+        let r1 = self.gen_expr(lhs);
+        let r2 = self.gen_expr(rhs);
         self.add(op, r1, r2);
+        self.kill(r2);
         r1
     }
 
-    fn gen_expr(&mut self, node: NodeType) -> Option<usize> {
-        match node {
-            NodeType::Num(val) => {
+    fn gen_expr(&mut self, node: Node) -> Option<usize> {
+        match node.kind {
+            NodeType::Num(val, _) => {
                 let r = Some(self.num_regs);
                 self.num_regs += 1;
                 self.add(IROp::Imm, r, Some(val as usize));
                 r
             },
-            NodeType::LVar(_) => {
-                let r = self.gen_lval(Box::new(node.clone()));
-                self.load(r, r);
+            NodeType::Str(data) => {
+                let r = Some(self.num_regs);
+                self.num_regs += 1;
+                let label = self.string_label(data);
+                self.add(IROp::LabelAddr(label), r, None);
+                r
+            },
+            NodeType::LVar(_) | NodeType::GVar(_) | NodeType::Deref(_) | NodeType::Member(..) => {
+                // 配列と構造体は先頭のアドレスそのものが値になる
+                if let Type::Array(..) | Type::Struct(_) = node.ty {
+                    return self.gen_lval(node);
+                }
+                let ty = node.ty.clone();
+                let r = self.gen_lval(node);
+                self.load(r, r, &ty);
+                r
+            },
+            NodeType::Addr(expr) => self.gen_lval(*expr),
+            NodeType::Plus(lhs, rhs) => self.gen_binop(IROp::Add, *lhs, *rhs),
+            NodeType::Minus(lhs, rhs) => self.gen_binop(IROp::Sub, *lhs, *rhs),
+            NodeType::Mul(lhs, rhs) => self.gen_binop(IROp::Mul, *lhs, *rhs),
+            NodeType::Div(lhs, rhs) if node.ty.is_unsigned() => self.gen_binop(IROp::UDiv, *lhs, *rhs),
+            NodeType::Div(lhs, rhs) => self.gen_binop(IROp::Div, *lhs, *rhs),
+            NodeType::Mod(lhs, rhs) if node.ty.is_unsigned() => self.gen_binop(IROp::UMod, *lhs, *rhs),
+            NodeType::Mod(lhs, rhs) => self.gen_binop(IROp::Mod, *lhs, *rhs),
+            NodeType::BitAnd(lhs, rhs) => self.gen_binop(IROp::And, *lhs, *rhs),
+            NodeType::BitOr(lhs, rhs) => self.gen_binop(IROp::Or, *lhs, *rhs),
+            NodeType::BitXor(lhs, rhs) => self.gen_binop(IROp::Xor, *lhs, *rhs),
+            NodeType::Shl(lhs, rhs) => self.gen_binop(IROp::Shl, *lhs, *rhs),
+            NodeType::Shr(lhs, rhs) if node.ty.is_unsigned() => self.gen_binop(IROp::Shr, *lhs, *rhs),
+            NodeType::Shr(lhs, rhs) => self.gen_binop(IROp::Sar, *lhs, *rhs),
+            NodeType::BitNot(expr) => {
+                let r = self.gen_expr(*expr);
+                self.add(IROp::Not, r, None);
+                r
+            },
+            // 8バイトへの変換は拡張済みなので何もしない
+            NodeType::Cast(expr) => {
+                let r = self.gen_expr(*expr);
+                if node.ty.is_integer() && node.ty.size() < 8 {
+                    self.add(IROp::Cast(node.ty.size() as u8, node.ty.is_unsigned()), r, None);
+                }
                 r
             },
-            NodeType::Plus(lhs, rhs) => self.gen_binop(IROp::Add, lhs, rhs),
-            NodeType::Minus(lhs, rhs) => self.gen_binop(IROp::Sub, lhs, rhs),
-            NodeType::Mul(lhs, rhs) => self.gen_binop(IROp::Mul, lhs, rhs),
-            NodeType::Div(lhs, rhs) => self.gen_binop(IROp::Div, lhs, rhs),
             NodeType::Assign(lhs, rhs) => {
+                let size = lhs.ty.size();
+                let is_struct = matches!(lhs.ty, Type::Struct(_));
                 let rhs = self.gen_expr(*rhs);
-                let lhs = self.gen_lval(lhs);
-                self.store(lhs, rhs);
+                let lhs = self.gen_lval(*lhs);
+                // 構造体の代入はメモリをそのままコピーする
+                if is_struct {
+                    self.add(IROp::Memcpy(size), lhs, rhs);
+                } else {
+                    self.store(lhs, rhs, size);
+                }
+                self.kill(lhs);
                 rhs
             },
-            NodeType::Eq(lhs, rhs) => self.gen_binop(IROp::Eq, lhs, rhs),
-            NodeType::Ne(lhs, rhs) => self.gen_binop(IROp::Ne, lhs, rhs),
-            NodeType::Lt(lhs, rhs) | NodeType::Gt(rhs, lhs) => self.gen_binop(IROp::Lt, lhs, rhs),
-            NodeType::Le(lhs, rhs) | NodeType::Ge(rhs, lhs) => self.gen_binop(IROp::Le, lhs, rhs),
-            _ => panic!("unknown node in expr：{:?}", node)
+            // 左辺で結果が決まれば右辺は評価しない
+            NodeType::LogAnd(lhs, rhs) => {
+                let x = Some(self.nlabel);
+                self.nlabel += 1;
+                let y = Some(self.nlabel);
+                self.nlabel += 1;
+                let r1 = self.gen_expr(*lhs);
+                self.add(IROp::Unless, r1, x);
+                let r2 = self.gen_expr(*rhs);
+                self.add(IROp::Unless, r2, x);
+                self.kill(r2);
+                self.add(IROp::Imm, r1, Some(1));
+                self.jmp(y);
+                self.label(x);
+                self.add(IROp::Imm, r1, Some(0));
+                self.label(y);
+                r1
+            },
+            NodeType::LogOr(lhs, rhs) => {
+                let x = Some(self.nlabel);
+                self.nlabel += 1;
+                let y = Some(self.nlabel);
+                self.nlabel += 1;
+                let z = Some(self.nlabel);
+                self.nlabel += 1;
+                let w = Some(self.nlabel);
+                self.nlabel += 1;
+                let r1 = self.gen_expr(*lhs);
+                self.add(IROp::Unless, r1, x);
+                self.jmp(y);
+                self.label(x);
+                let r2 = self.gen_expr(*rhs);
+                self.add(IROp::Unless, r2, z);
+                self.kill(r2);
+                self.label(y);
+                self.add(IROp::Imm, r1, Some(1));
+                self.jmp(w);
+                self.label(z);
+                self.add(IROp::Imm, r1, Some(0));
+                self.label(w);
+                r1
+            },
+            NodeType::Not(expr) => {
+                let r = self.gen_expr(*expr);
+                let zero = Some(self.num_regs);
+                self.num_regs += 1;
+                self.add(IROp::Imm, zero, Some(0));
+                self.add(IROp::Eq, r, zero);
+                self.kill(zero);
+                r
+            },
+            // どちらの枝でも同じレジスタに結果を入れる
+            NodeType::Cond(cond, then, els) => {
+                let x = Some(self.nlabel);
+                self.nlabel += 1;
+                let y = Some(self.nlabel);
+                self.nlabel += 1;
+                let r = Some(self.num_regs);
+                self.num_regs += 1;
+                let c = self.gen_expr(*cond);
+                self.add(IROp::Unless, c, x);
+                self.kill(c);
+                let t = self.gen_expr(*then);
+                self.add(IROp::Mov, r, t);
+                self.kill(t);
+                self.jmp(y);
+                self.label(x);
+                let e = self.gen_expr(*els);
+                self.add(IROp::Mov, r, e);
+                self.kill(e);
+                self.label(y);
+                r
+            },
+            NodeType::Comma(lhs, rhs) => {
+                let r = self.gen_expr(*lhs);
+                self.kill(r);
+                self.gen_expr(*rhs)
+            },
+            NodeType::Negative(expr) => {
+                let r = Some(self.num_regs);
+                self.num_regs += 1;
+                self.add(IROp::Imm, r, Some(0));
+                let e = self.gen_expr(*expr);
+                self.add(IROp::Sub, r, e);
+                self.kill(e);
+                r
+            },
+            NodeType::Call(name, args) => {
+                if args.len() > 6 {
+                    let message = format!("too many arguments to '{}': at most 6 are supported", name);
+                    self.errors.push(Diagnostic::error(E_UNSUPPORTED, node.span, message));
+                    return None;
+                }
+                if let Some(arg) = args.iter().find(|arg| matches!(arg.ty, Type::Struct(_))) {
+                    let message = "passing a struct by value is not supported".to_string();
+                    self.errors.push(Diagnostic::error(E_UNSUPPORTED, arg.span, message));
+                    return None;
+                }
+                // 引数は計算したそばからスタックに積み、呼び出す直前に引数レジスタへ移す
+                let nargs = args.len();
+                for arg in args {
+                    let r = self.gen_expr(arg);
+                    self.add(IROp::Push, r, None);
+                    self.kill(r);
+                }
+                let r = Some(self.num_regs);
+                self.num_regs += 1;
+                self.add(IROp::Call(name, nargs), r, None);
+                r
+            },
+            NodeType::Eq(lhs, rhs) => self.gen_binop(IROp::Eq, *lhs, *rhs),
+            NodeType::Ne(lhs, rhs) => self.gen_binop(IROp::Ne, *lhs, *rhs),
+            // 符号無し整数とポインタは符号無しで比べる
+            NodeType::Lt(lhs, rhs) | NodeType::Gt(rhs, lhs) => {
+                let op = if is_unsigned_cmp(&lhs) { IROp::ULt } else { IROp::Lt };
+                self.gen_binop(op, *lhs, *rhs)
+            },
+            NodeType::Le(lhs, rhs) | NodeType::Ge(rhs, lhs) => {
+                let op = if is_unsigned_cmp(&lhs) { IROp::ULe } else { IROp::Le };
+                self.gen_binop(op, *lhs, *rhs)
+            },
+            _ => self.unsupported(&node, "expr")
         }
     }
 
-    fn gen_stmt(&mut self, node: NodeType) {
-        match node {
+    fn gen_stmt(&mut self, node: Node) {
+        match node.kind {
             NodeType::Return(expr) => {
                 let r = self.gen_expr(*expr);
                 self.add(IROp::Return, r, None);
+                self.kill(r);
             },
             NodeType::If(cond, then, els_may) => {
                 if let Some(els) = els_may {
@@ -184,7 +485,8 @@ impl IrGenerator {
                     self.nlabel += 1;
                     let r = self.gen_expr(*cond);
                     self.add(IROp::Unless, r, x);
-                    self.gen_stmt(*then.clone());
+                    self.kill(r);
+                    self.gen_stmt(*then);
                     self.jmp(y);
                     self.label(x);
                     self.gen_stmt(*els);
@@ -196,18 +498,64 @@ impl IrGenerator {
                 self.nlabel += 1;
                 let r = self.gen_expr(*cond);
                 self.add(IROp::Unless, r, x);
+                self.kill(r);
                 self.gen_stmt(*then);
                 self.label(x);
-                },
-                NodeType::ExprStmt(expr) => {
-                self.gen_expr(*expr);
+            },
+            NodeType::While(cond, body) => {
+                let x = Some(self.nlabel);
+                self.nlabel += 1;
+                let y = Some(self.nlabel);
+                self.nlabel += 1;
+                self.label(x);
+                let r = self.gen_expr(*cond);
+                self.add(IROp::Unless, r, y);
+                self.kill(r);
+                self.gen_stmt(*body);
+                self.jmp(x);
+                self.label(y);
+            },
+            // 初期化部は宣言なら文、そうでなければ式
+            NodeType::For(init, cond, inc, body) => {
+                let x = Some(self.nlabel);
+                self.nlabel += 1;
+                let y = Some(self.nlabel);
+                self.nlabel += 1;
+                if let Some(init) = init {
+                    if let NodeType::Block(_) = init.kind {
+                        self.gen_stmt(*init);
+                    } else {
+                        let r = self.gen_expr(*init);
+                        self.kill(r);
+                    }
+                }
+                self.label(x);
+                // 条件を省略したら無限ループ
+                if let Some(cond) = cond {
+                    let r = self.gen_expr(*cond);
+                    self.add(IROp::Unless, r, y);
+                    self.kill(r);
+                }
+                self.gen_stmt(*body);
+                if let Some(inc) = inc {
+                    let r = self.gen_expr(*inc);
+                    self.kill(r);
+                }
+                self.jmp(x);
+                self.label(y);
+            },
+            NodeType::ExprStmt(expr) => {
+                let r = self.gen_expr(*expr);
+                self.kill(r);
             },
             NodeType::Block(stmts) => {
                 for stmt in stmts {
                     self.gen_stmt(stmt);
                 }
             },
-            _ => panic!("unknown node in stmt：{:?}", node)
+            _ => {
+                self.unsupported(&node, "stmt");
+            }
         }
     }
 }
@@ -1,25 +1,46 @@
-use crate::gen_ir::{Function, IROp, IR};
+use crate::gen_ir::{Function, Global, GlobalData, IROp, Program, IR};
+use crate::diagnostic::Diagnostic;
 
-const REGS: [&str; 7] = ["r10", "r11", "rbx", "r12", "r13", "r14", "r15"];
-const REGS8: [&str; 7] = ["r10b", "r11b", "bl", "r12b", "r13b", "r14b", "r15b"];
+pub const NUM_REGS: usize = 7;
+const REGS: [&str; NUM_REGS] = ["r10", "r11", "rbx", "r12", "r13", "r14", "r15"];
+const REGS8: [&str; NUM_REGS] = ["r10b", "r11b", "bl", "r12b", "r13b", "r14b", "r15b"];
+const REGS16: [&str; NUM_REGS] = ["r10w", "r11w", "bx", "r12w", "r13w", "r14w", "r15w"];
+const REGS32: [&str; NUM_REGS] = ["r10d", "r11d", "ebx", "r12d", "r13d", "r14d", "r15d"];
+const CALLEE_SAVED: [&str; 5] = ["rbx", "r12", "r13", "r14", "r15"];
 const ARGREGS: [&str; 6] = ["rdi", "rsi", "rdx", "rcx", "r8", "r9"];
+const ARGREGS8: [&str; 6] = ["dil", "sil", "dl", "cl", "r8b", "r9b"];
+const ARGREGS16: [&str; 6] = ["di", "si", "dx", "cx", "r8w", "r9w"];
+const ARGREGS32: [&str; 6] = ["edi", "esi", "edx", "ecx", "r8d", "r9d"];
 
 fn reg(r: usize, size: u8) -> &'static str {
     match size {
         1 => REGS8[r],
+        2 => REGS16[r],
+        4 => REGS32[r],
         8 => REGS[r],
         _ => unreachable!(),
     }
 }
 
+fn argreg(r: usize, size: u8) -> &'static str {
+    match size {
+        1 => ARGREGS8[r],
+        2 => ARGREGS16[r],
+        4 => ARGREGS32[r],
+        8 => ARGREGS[r],
+        _ => unreachable!(),
+    }
+}
+
 struct Generator {
     src: String,
     label: usize,
+    depth: usize,   // 積んだまままだ取り出していない引数の数
 }
 
 impl Generator {
     fn new() -> Self {
-        Self { src: String::new(), label:0 }
+        Self { src: String::new(), label:0, depth: 0 }
     }
 
     fn emit(&mut self, s: &str) {
@@ -34,39 +55,83 @@ impl Generator {
         self.emit(&format!("  movzb {}, {}", REGS[lhs], REGS8[lhs]));
     }
 
+    fn emit_shift(&mut self, lhs: usize, rhs: usize, s: &str) {
+        self.emit(&format!("  mov rcx, {}", REGS[rhs]));
+        self.emit(&format!("  {} {}, cl", s, REGS[lhs]));
+    }
+
     fn gen(&mut self, f: Function) {
         use self::IROp::*;
         let ret = format!(".Lend{}", self.label);
         self.label += 1;
 
-        self.emit(".intel_syntax noprefix");
-        self.emit(&format!(".global {}", f.name));
+        if !f.is_static {
+            self.emit(&format!(".globl {}", f.name));
+        }
         self.emit(&format!("{}:", f.name));
         self.emit("  push rbp");
         self.emit("  mov rbp, rsp");
+        // ローカル変数の領域は16バイト境界に揃える
+        let stacksize = f.stacksize.div_ceil(16) * 16;
+        if stacksize > 0 {
+            self.emit(&format!("  sub rsp, {}", stacksize));
+        }
+        for r in CALLEE_SAVED.iter() {
+            self.emit(&format!("  push {}", r));
+        }
+        // 5個pushした分のずれを直して、呼び出し時のrspを16の倍数にする
+        self.emit("  sub rsp, 8");
 
         for ir in f.code {
-            let lhs = ir.lhs.unwrap();
+            let lhs = ir.lhs.unwrap_or(0);
             let rhs = ir.rhs.unwrap_or(0);
             match ir.op {
-                Imm => self.emit(&format!("  mov {}, {}", REGS[lhs], rhs as i32)),
+                Imm => self.emit(&format!("  mov {}, {}", REGS[lhs], rhs as i64)),
+                LabelAddr(ref label) => self.emit(&format!("  lea {}, [rip+{}]", REGS[lhs], label)),
+                GlobalAddr(name) => self.emit(&format!("  lea {}, [rip+{}]", REGS[lhs], name)),
+                Mov => self.emit(&format!("  mov {}, {}", REGS[lhs], REGS[rhs])),
                 Add => self.emit(&format!("  add {}, {}", REGS[lhs], REGS[rhs])),
                 Sub => self.emit(&format!("  sub {}, {}", REGS[lhs], REGS[rhs])),
-                Mul => {
-                    self.emit(&format!("  mov rax, {}", REGS[rhs]));
-                    self.emit(&format!("  mul {}", REGS[lhs]));
+                Mul => self.emit(&format!("  imul {}, {}", REGS[lhs], REGS[rhs])),
+                Div => {
+                    self.emit(&format!("  mov rax, {}", REGS[lhs]));
+                    self.emit("  cqo");
+                    self.emit(&format!("  idiv {}", REGS[rhs]));
                     self.emit(&format!("  mov {}, rax", REGS[lhs]));
                 },
-                Div => {
+                UDiv => {
                     self.emit(&format!("  mov rax, {}", REGS[lhs]));
-                    self.emit("cqo");
+                    self.emit("  mov rdx, 0");
                     self.emit(&format!("  div {}", REGS[rhs]));
                     self.emit(&format!("  mov {}, rax", REGS[lhs]));
                 },
+                // 剰余はrdxに残る
+                Mod => {
+                    self.emit(&format!("  mov rax, {}", REGS[lhs]));
+                    self.emit("  cqo");
+                    self.emit(&format!("  idiv {}", REGS[rhs]));
+                    self.emit(&format!("  mov {}, rdx", REGS[lhs]));
+                },
+                UMod => {
+                    self.emit(&format!("  mov rax, {}", REGS[lhs]));
+                    self.emit("  mov rdx, 0");
+                    self.emit(&format!("  div {}", REGS[rhs]));
+                    self.emit(&format!("  mov {}, rdx", REGS[lhs]));
+                },
+                And => self.emit(&format!("  and {}, {}", REGS[lhs], REGS[rhs])),
+                Or => self.emit(&format!("  or {}, {}", REGS[lhs], REGS[rhs])),
+                Xor => self.emit(&format!("  xor {}, {}", REGS[lhs], REGS[rhs])),
+                // シフト量はclでしか指定できない
+                Shl => self.emit_shift(lhs, rhs, "shl"),
+                Shr => self.emit_shift(lhs, rhs, "shr"),
+                Sar => self.emit_shift(lhs, rhs, "sar"),
+                Not => self.emit(&format!("  not {}", REGS[lhs])),
                 Eq => self.emit_cmp(ir, "sete"),
                 Ne => self.emit_cmp(ir, "setne"),
                 Lt => self.emit_cmp(ir, "setl"),
                 Le => self.emit_cmp(ir, "setle"),
+                ULt => self.emit_cmp(ir, "setb"),
+                ULe => self.emit_cmp(ir, "setbe"),
                 Label => self.emit(&format!(".L{}:", lhs)),
                 Return => {
                     self.emit(&format!("  mov rax, {}", REGS[lhs]));
@@ -77,29 +142,129 @@ impl Generator {
                     self.emit(&format!("  cmp {}, 0", REGS[lhs]));
                     self.emit(&format!("  je .L{}", rhs));
                 },
-                Load(size) => {
-                    self.emit(&format!("  mov {}, [{}]", reg(lhs, size), REGS[rhs]));
-                    if size == 1 {
-                        self.emit(&format!("  movzb {}, {}", REGS[lhs], REGS8[lhs]));
-                    }
+                // 8バイトより小さい値は符号拡張かゼロ拡張して読む
+                // 32ビットレジスタへのmovは上位を0にする
+                Load(size, is_unsigned) => match (size, is_unsigned) {
+                    (1, false) => self.emit(&format!("  movsx {}, byte ptr [{}]", REGS[lhs], REGS[rhs])),
+                    (1, true) => self.emit(&format!("  movzx {}, byte ptr [{}]", REGS[lhs], REGS[rhs])),
+                    (2, false) => self.emit(&format!("  movsx {}, word ptr [{}]", REGS[lhs], REGS[rhs])),
+                    (2, true) => self.emit(&format!("  movzx {}, word ptr [{}]", REGS[lhs], REGS[rhs])),
+                    (4, false) => self.emit(&format!("  movsxd {}, dword ptr [{}]", REGS[lhs], REGS[rhs])),
+                    (4, true) => self.emit(&format!("  mov {}, dword ptr [{}]", REGS32[lhs], REGS[rhs])),
+                    _ => self.emit(&format!("  mov {}, [{}]", REGS[lhs], REGS[rhs])),
+                },
+                Cast(size, is_unsigned) => match (size, is_unsigned) {
+                    (1, false) => self.emit(&format!("  movsx {}, {}", REGS[lhs], REGS8[lhs])),
+                    (1, true) => self.emit(&format!("  movzx {}, {}", REGS[lhs], REGS8[lhs])),
+                    (2, false) => self.emit(&format!("  movsx {}, {}", REGS[lhs], REGS16[lhs])),
+                    (2, true) => self.emit(&format!("  movzx {}, {}", REGS[lhs], REGS16[lhs])),
+                    (4, false) => self.emit(&format!("  movsxd {}, {}", REGS[lhs], REGS32[lhs])),
+                    (4, true) => self.emit(&format!("  mov {}, {}", REGS32[lhs], REGS32[lhs])),
+                    _ => {},
                 },
                 Store(size) => self.emit(&format!("  mov [{}], {}", REGS[lhs], reg(rhs, size))),
                 Bprel => self.emit(&format!("  lea {}, [rbp-{}]", REGS[lhs], rhs)),
-                _ => {}
+                Memcpy(size) => {
+                    // 8, 4, 2, 1バイトずつraxを経由してコピーする
+                    let mut off = 0;
+                    for (n, r, word) in [(8, "rax", "qword"), (4, "eax", "dword"), (2, "ax", "word"), (1, "al", "byte")] {
+                        while size - off >= n {
+                            self.emit(&format!("  mov {}, {} ptr [{}+{}]", r, word, REGS[rhs], off));
+                            self.emit(&format!("  mov {} ptr [{}+{}], {}", word, REGS[lhs], off, r));
+                            off += n;
+                        }
+                    }
+                },
+                StoreArg(size) => self.emit(&format!("  mov [rbp-{}], {}", lhs, argreg(rhs, size))),
+                Spill => self.emit(&format!("  mov [rbp-{}], {}", rhs, REGS[lhs])),
+                Reload => self.emit(&format!("  mov {}, [rbp-{}]", REGS[lhs], rhs)),
+                Push => {
+                    self.emit(&format!("  push {}", REGS[lhs]));
+                    self.depth += 1;
+                },
+                Call(name, nargs) => {
+                    for i in (0..nargs).rev() {
+                        self.emit(&format!("  pop {}", ARGREGS[i]));
+                    }
+                    self.depth -= nargs;
+                    // r10とr11は呼び出し先で壊されるので退避する
+                    self.emit("  push r10");
+                    self.emit("  push r11");
+                    // 外側の呼び出しの引数が奇数個積まれていたらrspを16の倍数に揃える
+                    let pad = self.depth % 2 == 1;
+                    if pad {
+                        self.emit("  sub rsp, 8");
+                    }
+                    self.emit("  mov rax, 0");
+                    self.emit(&format!("  call {}", name));
+                    if pad {
+                        self.emit("  add rsp, 8");
+                    }
+                    self.emit("  pop r11");
+                    self.emit("  pop r10");
+                    self.emit(&format!("  mov {}, rax", REGS[lhs]));
+                },
+                Kill => {},
             }
         }
 
         self.emit(&format!("{}:", ret));
+        self.emit("  add rsp, 8");
+        for r in CALLEE_SAVED.iter().rev() {
+            self.emit(&format!("  pop {}", r));
+        }
         self.emit("  mov rsp, rbp");
         self.emit("  pop rbp");
         self.emit("  ret")
     }
+
+    // 初期値のない変数は.bss、ある変数は.dataに置く
+    fn gen_global(&mut self, g: &Global) {
+        if g.is_static {
+            self.emit(&format!(".local {}", g.name));
+        } else {
+            self.emit(&format!(".globl {}", g.name));
+        }
+        match g.data {
+            GlobalData::Zero => self.emit(".bss"),
+            _ => self.emit(".data"),
+        }
+        self.emit(&format!(".align {}", g.align));
+        self.emit(&format!("{}:", g.name));
+        match g.data {
+            GlobalData::Zero => self.emit(&format!("  .zero {}", g.size)),
+            GlobalData::Bytes(ref data) => {
+                let bytes: Vec<String> = data.iter().map(|b| b.to_string()).collect();
+                self.emit(&format!("  .byte {}", bytes.join(", ")));
+            },
+            GlobalData::Label(ref label) => self.emit(&format!("  .quad {}", label)),
+        }
+    }
+
+    // 文字列リテラルは末尾に\0を付けて.rodataに置く
+    fn gen_strings(&mut self, strings: &[Vec<u8>]) {
+        if strings.is_empty() {
+            return;
+        }
+        self.emit(".section .rodata");
+        for (i, data) in strings.iter().enumerate() {
+            let bytes: Vec<String> = data.iter().chain([0].iter()).map(|b| b.to_string()).collect();
+            self.emit(&format!(".LC{}:", i));
+            self.emit(&format!("  .byte {}", bytes.join(", ")));
+        }
+    }
 }
 
-pub fn gen_x86(fns: Vec<Function>) -> String {
+pub fn gen_x86(program: Program) -> Result<String, Vec<Diagnostic>> {
     let mut generator = Generator::new();
-    for f in fns {
+    generator.emit(".intel_syntax noprefix");
+    generator.gen_strings(&program.strings);
+    for g in program.globals.iter() {
+        generator.gen_global(g);
+    }
+    generator.emit(".text");
+    for f in program.funcs {
         generator.gen(f);
     }
-    generator.src
+    Ok(generator.src)
 }
\ No newline at end of file
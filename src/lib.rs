@@ -1,15 +1,70 @@
+use std::fmt;
+
+use types::Type;
+use symbol::Symbol;
+
 pub mod token;
 pub mod parse;
+pub mod gen_ir;
+pub mod regalloc;
+pub mod gen_x86;
+pub mod diagnostic;
+pub mod types;
+pub mod symbol;
+
+// ソース上の位置
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Span {
+    pub file: usize,    // ファイルID
+    pub offset: usize,  // 先頭からのバイトオフセット
+    pub len: usize,     // バイト長
+    pub line: usize,    // 行番号(1始まり)
+    pub col: usize,     // 列番号(1始まり)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Token {
+    pub kind: TokenType,
+    pub span: Span,
+}
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum TokenType {
-    Num(i32),       // 数値
-    Ident(String),  // 識別子(変数名)
+    Num(i64, Type), // 数値
+    Ident(Symbol),  // 識別子(変数名)
+    Str(Vec<u8>),   // 文字列リテラル
+    CharLit(i32),   // 文字定数
     Return,         // return
+    Void,           // void
+    Char,           // char
+    Short,          // short
+    Int,            // int
+    Long,           // long
+    Signed,         // signed
+    Unsigned,       // unsigned
+    Sizeof,         // sizeof
+    Static,         // static
+    Struct,         // struct
+    Union,          // union
+    Enum,           // enum
+    Typedef,        // typedef
+    Alignof,        // _Alignof
     Plus,           // +
     Minus,          // -
     Mul,            // *
     Div,            // /
+    Mod,            // %
+    Not,            // !
+    BitNot,         // ~
+    BitAnd,         // &
+    BitOr,          // |
+    BitXor,         // ^
+    LogAnd,         // &&
+    LogOr,          // ||
+    Shl,            // <<
+    Shr,            // >>
+    Inc,            // ++
+    Dec,            // --
     If,             // if
     Else,           // else
     While,          // while
@@ -21,44 +76,113 @@ pub enum TokenType {
     Lt,             // <
     Gt,             // >
     Assign,         // =
+    AddAssign,      // +=
+    SubAssign,      // -=
+    MulAssign,      // *=
+    DivAssign,      // /=
+    ModAssign,      // %=
+    AndAssign,      // &=
+    OrAssign,       // |=
+    XorAssign,      // ^=
+    ShlAssign,      // <<=
+    ShrAssign,      // >>=
+    Arrow,          // ->
+    Dot,            // .
+    Question,       // ?
+    Colon,          // :
+    Ellipsis,       // ...
+    Hash,           // #
+    HashHash,       // ##
     Semicolon,      // ;
-    Comma,           // ,
+    Comma,          // ,
     LeftParen,      // (
     RightParen,     // )
     LeftBrace,      // {
     RightBrace,     // }
+    LeftBracket,    // [
+    RightBracket,   // ]
     NewLine,        // 改行
+    Eof,            // 入力の終端
 }
 
-impl TokenType {
-    pub fn new_single_letter(c: char) -> Option<Self> {
+impl fmt::Display for TokenType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         use self::TokenType::*;
-        match c {
-            '+' => Some(Plus),
-            '-' => Some(Minus),
-            '*' => Some(Mul),
-            '/' => Some(Div),
-            '=' => Some(Assign),
-            '<' => Some(Lt),
-            '>' => Some(Gt),
-            ';' => Some(Semicolon),
-            ',' => Some(Comma),
-            '(' => Some(LeftParen),
-            ')' => Some(RightParen),
-            '{' => Some(LeftBrace),
-            '}' => Some(RightBrace),
-            _ => None,
-        }
+        let s = match self {
+            Num(val, _) => return write!(f, "{}", val),
+            Ident(name) => return write!(f, "{}", name),
+            Str(s) => return write!(f, "{:?}", String::from_utf8_lossy(s)),
+            CharLit(c) => return write!(f, "character constant {}", c),
+            Return => "return",
+            Void => "void",
+            Char => "char",
+            Short => "short",
+            Int => "int",
+            Long => "long",
+            Signed => "signed",
+            Unsigned => "unsigned",
+            Sizeof => "sizeof",
+            Static => "static",
+            Struct => "struct",
+            Union => "union",
+            Enum => "enum",
+            Typedef => "typedef",
+            Alignof => "_Alignof",
+            Plus => "+",
+            Minus => "-",
+            Mul => "*",
+            Div => "/",
+            Mod => "%",
+            Not => "!",
+            BitNot => "~",
+            BitAnd => "&",
+            BitOr => "|",
+            BitXor => "^",
+            LogAnd => "&&",
+            LogOr => "||",
+            Shl => "<<",
+            Shr => ">>",
+            Inc => "++",
+            Dec => "--",
+            If => "if",
+            Else => "else",
+            While => "while",
+            For => "for",
+            Eq => "==",
+            Ne => "!=",
+            Le => "<=",
+            Ge => ">=",
+            Lt => "<",
+            Gt => ">",
+            Assign => "=",
+            AddAssign => "+=",
+            SubAssign => "-=",
+            MulAssign => "*=",
+            DivAssign => "/=",
+            ModAssign => "%=",
+            AndAssign => "&=",
+            OrAssign => "|=",
+            XorAssign => "^=",
+            ShlAssign => "<<=",
+            ShrAssign => ">>=",
+            Arrow => "->",
+            Dot => ".",
+            Question => "?",
+            Colon => ":",
+            Ellipsis => "...",
+            Hash => "#",
+            HashHash => "##",
+            Semicolon => ";",
+            Comma => ",",
+            LeftParen => "(",
+            RightParen => ")",
+            LeftBrace => "{",
+            RightBrace => "}",
+            LeftBracket => "[",
+            RightBracket => "]",
+            NewLine => "new line",
+            Eof => "end of file",
+        };
+        write!(f, "{}", s)
     }
 }
-
-#[derive(Debug, PartialEq)]
-pub enum CharacterType {
-    Whitespace,     // ' '
-    NewLine,        // \n
-    CarriageReturn, // \r
-    Alphabetic,
-    Digit,
-    NonAlphabetic(char),
-    Unknown(char),
-}
\ No newline at end of file
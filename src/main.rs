@@ -1,62 +1,87 @@
 extern crate rustcc;
-use rustcc::*;
+use rustcc::token::*;
+use rustcc::parse::*;
+use rustcc::gen_ir::*;
+use rustcc::regalloc::*;
+use rustcc::gen_x86::*;
+use rustcc::diagnostic::SourceMap;
 
 use std::env;
+use std::process;
 use std::io::Write;
 use std::fs::File;
 use std::io::prelude::*;
 
-mod token;
-mod parse;
-mod gen_ir;
-mod gen_x86;
-
-use token::*;
-use parse::*;
-use gen_ir::*;
-use gen_x86::*;
-
 fn main() {
     let args: Vec<String> = env::args().collect();
     if args.len() < 2 {
         eprintln!("invalid arguments");
-        return;
+        process::exit(1);
     }
 
     let mut is_print_contents = false;
     let mut is_print_asm = false;
+    let mut is_print_layout = false;
     let mut is_file = true;
     let mut input = String::new();
     for arg in args[1..].iter() {
         // cargo runでは "-" 始まりの引数を渡せないためエスケープ用の文字を追加
-        let mut arg: &str = arg;
-        if &arg[0..1] == "^" {
-            arg = &arg[1..];
-        }
+        let arg = arg.strip_prefix('^').unwrap_or(arg);
         match arg {
             "-i" => is_print_contents = true,
             "-a" => is_print_asm = true,
+            "-l" => is_print_layout = true,
             "-f" => is_file = false,
             _ => input = arg.to_string()
         }
     }
 
-    compile(input, is_file, is_print_contents, is_print_asm);
+    if !compile(input, is_file, is_print_contents, is_print_asm, is_print_layout) {
+        process::exit(1);
+    }
 }
 
-fn compile(input: String, is_file: bool, is_print_contents: bool, is_print_asm: bool) {
+fn compile(input: String, is_file: bool, is_print_contents: bool, is_print_asm: bool, is_print_layout: bool) -> bool {
     let mut contents = String::new();
+    let name;
     if is_file {
-        let mut f = File::open(input).expect("file not found");
-        f.read_to_string(&mut contents).expect("failure read file");
+        let read = File::open(&input).and_then(|mut f| f.read_to_string(&mut contents));
+        if let Err(e) = read {
+            eprintln!("{}: {}", input, e);
+            return false;
+        }
+        name = input;
     } else {
         contents = input;
+        name = "<input>".to_string();
     }
 
-    let tokens = tokenize(contents.as_str());
-    let nodes = parse(&tokens);
-    let ir = gen_ir(nodes);
-    let asm = gen_x86(ir);
+    let mut sources = SourceMap::new();
+    let file = sources.add(name, contents.clone());
+
+    let result = tokenize(sources.get(file))
+        .and_then(|tokens| parse_with_records(&tokens))
+        .map(|(nodes, records)| {
+            // 構造体のメンバーの配置を確認する
+            if is_print_layout {
+                for record in records.iter() {
+                    println!("{}", record.layout());
+                }
+            }
+            nodes
+        })
+        .and_then(gen_ir)
+        .and_then(alloc_regs)
+        .and_then(gen_x86);
+    let asm = match result {
+        Ok(asm) => asm,
+        Err(diagnostics) => {
+            for d in diagnostics.iter() {
+                eprintln!("{}", d.render(&sources));
+            }
+            return false;
+        }
+    };
 
     if is_print_contents {
         println!("------- read contents -------");
@@ -69,11 +94,14 @@ fn compile(input: String, is_file: bool, is_print_contents: bool, is_print_asm:
         println!("-------------------------------\n");
     }
 
-    if let Ok(v) = File::create("./tmp.s") {
-        let mut file = v;
-        match file.write_all(asm.as_bytes()) {
-            Ok(()) => println!("success"),
-            Err(_) => println!("failure")
+    match File::create("./tmp.s").and_then(|mut file| file.write_all(asm.as_bytes())) {
+        Ok(()) => {
+            println!("success");
+            true
+        },
+        Err(_) => {
+            println!("failure");
+            false
         }
     }
 }
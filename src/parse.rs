@@ -1,367 +1,1895 @@
-use crate::TokenType;
+use crate::{Span, Token, TokenType};
+use crate::types::{Member, Record, Type};
+use crate::symbol::Symbol;
+use crate::diagnostic::{Diagnostic, E_UNEXPECTED_TOKEN, E_UNDECLARED, E_REDEFINITION, E_UNSUPPORTED};
+use std::collections::HashMap;
 
-pub fn parse(tokens: &Vec<TokenType>) -> Vec<NodeType> {
-    let locals: &mut Vec<LVar> = &mut vec![];
-    let mut parser = Parser::new(tokens, locals);
+type PResult<T> = Result<T, Diagnostic>;
+
+pub fn parse(tokens: &[Token]) -> Result<Vec<Node>, Vec<Diagnostic>> {
+    parse_with_records(tokens).map(|(nodes, _)| nodes)
+}
+
+// 定義された構造体・共用体の型も一緒に返す
+pub fn parse_with_records(tokens: &[Token]) -> Result<(Vec<Node>, Vec<Record>), Vec<Diagnostic>> {
+    let mut parser = Parser::new(tokens);
 
     let mut nodes = vec![];
-    while tokens.len() != parser.pos {
-        nodes.push(parser.toplevel());
+    while !parser.at_eof() {
+        match parser.toplevel() {
+            Ok(mut node) => nodes.append(&mut node),
+            Err(e) => {
+                parser.report(e);
+                parser.skip_toplevel();
+            }
+        }
+    }
+    if !parser.errors.is_empty() {
+        return Err(parser.errors);
+    }
+    Ok((nodes, parser.records))
+}
+
+// 同じ型なら変換しない
+fn new_cast(node: Node, ty: Type) -> Node {
+    if node.ty == ty {
+        return node;
+    }
+    let span = node.span;
+    Node::with_type(NodeType::Cast(Box::new(node)), ty, span)
+}
+
+// 配列は先頭要素へのポインタに変換する
+fn decay(node: Node) -> Node {
+    match node.ty {
+        Type::Array(ref base, _) => {
+            let ty = base.clone().ptr_to();
+            new_cast(node, ty)
+        },
+        _ => node,
+    }
+}
+
+// 整数、ポインタ、配列(ポインタとして扱う)
+fn is_scalar(ty: &Type) -> bool {
+    ty.is_integer() || ty.base().is_some()
+}
+
+// ポインタと比べたり混ぜたりできる整数の0
+fn is_null(node: &Node) -> bool {
+    matches!(node.kind, NodeType::Num(0, _))
+}
+
+// 整数の演算はすべて64ビットで行う
+fn widen(ty: &Type) -> Type {
+    match ty {
+        _ if ty.size() == 8 => ty.clone(),
+        _ if ty.is_unsigned() => Type::ULong,
+        _ => Type::Long,
+    }
+}
+
+// 整数の代入では右辺を左辺の型に変換する
+fn new_assign(lhs: Node, rhs: Node, span: Span) -> Node {
+    let rhs = if lhs.ty.is_integer() && rhs.ty.is_integer() { new_cast(rhs, lhs.ty.clone()) } else { rhs };
+    Node::new(NodeType::Assign(Box::new(lhs), Box::new(rhs)), span)
+}
+
+// 定数を型の大きさに切り詰める
+fn truncate(val: i64, ty: &Type) -> i64 {
+    match ty {
+        Type::Char => val as i8 as i64,
+        Type::UChar => val as u8 as i64,
+        Type::Short => val as i16 as i64,
+        Type::UShort => val as u16 as i64,
+        Type::Int => val as i32 as i64,
+        Type::UInt => val as u32 as i64,
+        _ => val,
+    }
+}
+
+fn align_to(n: i32, align: i32) -> i32 {
+    (n + align - 1) / align * align
+}
+
+#[derive(Debug, Clone)]
+pub struct Node {
+    pub kind: NodeType,
+    pub ty: Type,   // 式の型(文はvoid)
+    pub span: Span,
+}
+
+impl Node {
+    // 型は子ノードから決める
+    fn new(kind: NodeType, span: Span) -> Self {
+        use self::NodeType::*;
+        let ty = match &kind {
+            Num(_, ty) => ty.clone(),
+            Str(data) => Type::Array(Box::new(Type::Char), data.len() + 1),
+            // ポインタと整数の加減算はポインタになる
+            // 配列は先頭要素へのポインタとして扱う
+            Plus(lhs, _) | Minus(lhs, _) if lhs.ty.base().is_some() => lhs.ty.base().unwrap().clone().ptr_to(),
+            Plus(lhs, rhs) | Minus(lhs, rhs) | Mul(lhs, rhs) | Div(lhs, rhs) => Type::arith(&lhs.ty, &rhs.ty),
+            Eq(..) | Ne(..) | Le(..) | Lt(..) | Ge(..) | Gt(..) => Type::Int,
+            LogAnd(..) | LogOr(..) | Not(_) => Type::Int,
+            Negative(expr) => expr.ty.promote(),
+            Addr(expr) => expr.ty.clone().ptr_to(),
+            Deref(expr) => expr.ty.base().cloned().unwrap_or(Type::Int),
+            Assign(lhs, _) => lhs.ty.clone(),
+            Comma(_, rhs) => rhs.ty.clone(),
+            _ => Type::Void,
+        };
+        Node { kind, ty, span }
+    }
+
+    // 変数や関数呼び出しのように、型を別に持っているノード
+    fn with_type(kind: NodeType, ty: Type, span: Span) -> Self {
+        Node { kind, ty, span }
     }
-    nodes
 }
 
 #[derive(Debug, Clone)]
 pub enum NodeType {
-    Num(i32),       // 数値
-    Plus(Box<NodeType>, Box<NodeType>),
-    Minus(Box<NodeType>, Box<NodeType>),
-    Mul(Box<NodeType>, Box<NodeType>),
-    Div(Box<NodeType>, Box<NodeType>),
-    Eq(Box<NodeType>, Box<NodeType>),
-    Ne(Box<NodeType>, Box<NodeType>),
-    Le(Box<NodeType>, Box<NodeType>),
-    Lt(Box<NodeType>, Box<NodeType>),
-    Ge(Box<NodeType>, Box<NodeType>),
-    Gt(Box<NodeType>, Box<NodeType>),
-    Negative(Box<NodeType>),        // -
+    Num(i64, Type), // 数値
+    Str(Vec<u8>),   // 文字列リテラル(末尾の\0は含まない)
+    Plus(Box<Node>, Box<Node>),
+    Minus(Box<Node>, Box<Node>),
+    Mul(Box<Node>, Box<Node>),
+    Div(Box<Node>, Box<Node>),
+    Mod(Box<Node>, Box<Node>),
+    BitAnd(Box<Node>, Box<Node>),
+    BitOr(Box<Node>, Box<Node>),
+    BitXor(Box<Node>, Box<Node>),
+    Shl(Box<Node>, Box<Node>),
+    Shr(Box<Node>, Box<Node>),
+    BitNot(Box<Node>),          // ~
+    Eq(Box<Node>, Box<Node>),
+    Ne(Box<Node>, Box<Node>),
+    Le(Box<Node>, Box<Node>),
+    Lt(Box<Node>, Box<Node>),
+    Ge(Box<Node>, Box<Node>),
+    Gt(Box<Node>, Box<Node>),
+    LogAnd(Box<Node>, Box<Node>),
+    LogOr(Box<Node>, Box<Node>),
+    Not(Box<Node>),             // !
+    Negative(Box<Node>),        // -
+    Addr(Box<Node>),            // &
+    Deref(Box<Node>),           // *
+    Cast(Box<Node>),            // 変換先の型はノードの型
     LVar(i32),
-    Call(String, Vec<NodeType>),
-    Func(String, Vec<NodeType>, Box<NodeType>, usize),  // ident, args, body, stacksize
-    Assign(Box<NodeType>, Box<NodeType>),
-    Return(Box<NodeType>),
-    If(Box<NodeType>, Box<NodeType>, Option<Box<NodeType>>),
-    While(Box<NodeType>, Box<NodeType>),
-    For(Option<Box<NodeType>>, Option<Box<NodeType>>, Option<Box<NodeType>>, Box<NodeType>),
-    Block(Vec<NodeType>),
-    ExprStmt(Box<NodeType>),
-    CompStmt(Vec<NodeType>),
+    GVar(Symbol),
+    Member(Box<Node>, usize),   // 構造体, メンバーのオフセット
+    Call(Symbol, Vec<Node>),
+    Func(Symbol, Vec<Node>, Box<Node>, usize, bool),  // ident, args, body, stacksize, is_static
+    GVarDef(Symbol, Init, bool),    // ident, 初期値, is_static
+    Assign(Box<Node>, Box<Node>),
+    Comma(Box<Node>, Box<Node>),    // 左辺を評価してから右辺の値になる
+    Cond(Box<Node>, Box<Node>, Box<Node>),  // cond ? then : else
+    Return(Box<Node>),
+    If(Box<Node>, Box<Node>, Option<Box<Node>>),
+    While(Box<Node>, Box<Node>),
+    For(Option<Box<Node>>, Option<Box<Node>>, Option<Box<Node>>, Box<Node>),
+    Block(Vec<Node>),
+    ExprStmt(Box<Node>),
+    CompStmt(Vec<Node>),
+}
+
+impl NodeType {
+    // 診断メッセージ用の名前
+    pub fn name(&self) -> &'static str {
+        use self::NodeType::*;
+        match self {
+            Num(..) => "number",
+            Str(_) => "string literal",
+            Plus(..) => "'+'",
+            Minus(..) => "'-'",
+            Mul(..) => "'*'",
+            Div(..) => "'/'",
+            Mod(..) => "'%'",
+            BitAnd(..) => "'&'",
+            BitOr(..) => "'|'",
+            BitXor(..) => "'^'",
+            Shl(..) => "'<<'",
+            Shr(..) => "'>>'",
+            BitNot(_) => "'~'",
+            Eq(..) => "'=='",
+            Ne(..) => "'!='",
+            Le(..) => "'<='",
+            Lt(..) => "'<'",
+            Ge(..) => "'>='",
+            Gt(..) => "'>'",
+            LogAnd(..) => "'&&'",
+            LogOr(..) => "'||'",
+            Not(_) => "'!'",
+            Negative(_) => "unary '-'",
+            Addr(_) => "unary '&'",
+            Deref(_) => "unary '*'",
+            Cast(_) => "cast",
+            LVar(_) | GVar(_) => "variable",
+            Member(..) => "member access",
+            Call(..) => "function call",
+            Func(..) => "function definition",
+            GVarDef(..) => "global variable definition",
+            Assign(..) => "'='",
+            Comma(..) => "','",
+            Cond(..) => "'?:'",
+            Return(_) => "return statement",
+            If(..) => "if statement",
+            While(..) => "while statement",
+            For(..) => "for statement",
+            Block(_) => "block",
+            ExprStmt(_) => "expression statement",
+            CompStmt(_) => "compound statement",
+        }
+    }
+}
+
+// グローバル変数の初期値
+#[derive(Debug, Clone)]
+pub enum Init {
+    Zero,           // 初期値なし(.bss)
+    Int(i64),       // 整数の定数
+    Str(Vec<u8>),   // 文字列リテラル(char配列なら中身、ポインタならそのアドレス)
 }
 
 #[derive(Debug, Clone)]
 pub struct LVar {
-    pub name: String,
-    pub len: i32,
+    pub name: Symbol,
+    pub ty: Type,
     pub offset: i32,
 }
 
+// ブロックごとの変数、列挙定数、typedef名と構造体のタグ
+#[derive(Default)]
+struct Scope {
+    vars: Vec<LVar>,
+    consts: Vec<(Symbol, i64)>,
+    typedefs: Vec<(Symbol, Type)>,
+    tags: Vec<(Symbol, Record)>,
+    enums: Vec<Symbol>,         // 列挙型のタグ
+}
+
 pub struct Parser<'a> {
-    tokens: &'a Vec<TokenType>,
+    tokens: &'a [Token],
     pos: usize,
-    locals: &'a mut Vec<LVar>,
+    scopes: Vec<Scope>,      // 先頭はファイル全体、内側のブロックほど後ろ
+    offset: i32,             // 現在のスタックの深さ
+    stacksize: i32,          // 関数内で一番深くなった時のスタックの深さ
+    ret: Type,                      // 解析中の関数の戻り値の型
+    funcs: HashMap<Symbol, Type>,   // 定義済みの関数の戻り値の型
+    globals: HashMap<Symbol, Type>, // 定義済みのグローバル変数の型
+    records: Vec<Record>,           // 定義された構造体・共用体
+    errors: Vec<Diagnostic>,
 }
 
 impl<'a> Parser<'a> {
-    pub fn new(tokens: &'a Vec<TokenType>, locals: &'a mut Vec<LVar>) -> Self {
+    pub fn new(tokens: &'a [Token]) -> Self {
         Parser {
             tokens,
             pos: 0,
-            locals,
+            scopes: vec![Scope::default()],
+            offset: 0,
+            stacksize: 0,
+            ret: Type::Void,
+            funcs: HashMap::new(),
+            globals: HashMap::new(),
+            records: vec![],
+            errors: vec![],
         }
     }
 
-    fn find_lvar(&self, ty: TokenType) -> Option<&LVar> {
-        let ident = match ty {
-            TokenType::Ident(name) => name,
-            _ => return None,
+    fn enter_scope(&mut self) {
+        self.scopes.push(Scope::default());
+    }
+
+    // ブロックを抜けたら、そのブロックの変数の領域は後の変数で使い回す
+    fn leave_scope(&mut self) {
+        self.scopes.pop();
+        self.offset = self.scopes.iter().flat_map(|scope| scope.vars.iter()).map(|var| var.offset).max().unwrap_or(0);
+    }
+
+    fn find_tag(&self, tag: Symbol) -> Option<&Record> {
+        self.scopes.iter().rev().flat_map(|scope| scope.tags.iter().rev()).find(|t| t.0 == tag).map(|t| &t.1)
+    }
+
+    // 現在のスコープに変数を追加する
+    // 外側のスコープの同名の変数は隠れる
+    fn declare(&mut self, name: Symbol, ty: Type, span: Span) -> PResult<Node> {
+        if !ty.is_complete() {
+            let message = format!("variable '{}' has incomplete type '{}'", name, ty);
+            return Err(self.error(span, message));
+        }
+        self.check_redefinition(name, span)?;
+        // 置けなくても名前は登録して、後の未宣言エラーを出さない
+        let alloc = self.alloc_local(&ty, span);
+        let lvar = LVar {
+            name,
+            ty: ty.clone(),
+            offset: self.offset,
         };
-        for var in self.locals.iter() {
-            if var.name == ident {
-                return Some(var);
+        self.scopes.last_mut().unwrap().vars.push(lvar);
+        alloc?;
+        Ok(Node::with_type(NodeType::LVar(self.offset), ty, span))
+    }
+
+    // 式の途中の値を置く名前のない変数
+    fn new_temp(&mut self, ty: Type, span: Span) -> PResult<Node> {
+        self.alloc_local(&ty, span)?;
+        Ok(Node::with_type(NodeType::LVar(self.offset), ty, span))
+    }
+
+    // 型の大きさとアラインメントに合わせてスタックに場所を取る
+    // rbpからのオフセットはi32に収まらなければならない
+    fn alloc_local(&mut self, ty: &Type, span: Span) -> PResult<()> {
+        let end = (self.offset as u64).saturating_add(ty.size() as u64);
+        if end > i32::MAX as u64 - ty.align() as u64 {
+            let message = format!("stack frame is too large: '{}' does not fit", ty);
+            return Err(Diagnostic::error(E_UNSUPPORTED, span, message));
+        }
+        self.offset = align_to(end as i32, ty.align() as i32);
+        self.stacksize = self.stacksize.max(self.offset);
+        Ok(())
+    }
+
+    // 変数、列挙定数、typedef名は同じ名前空間
+    // ファイル全体のスコープではグローバル変数と関数も同じ名前空間
+    fn check_redefinition(&self, name: Symbol, span: Span) -> PResult<()> {
+        let scope = self.scopes.last().unwrap();
+        let is_file_scope = self.scopes.len() == 1;
+        if scope.vars.iter().any(|var| var.name == name)
+            || scope.consts.iter().any(|c| c.0 == name)
+            || scope.typedefs.iter().any(|t| t.0 == name)
+            || (is_file_scope && (self.globals.contains_key(&name) || self.funcs.contains_key(&name))) {
+            let message = format!("redefinition of '{}'", name);
+            return Err(Diagnostic::error(E_REDEFINITION, span, message));
+        }
+        Ok(())
+    }
+
+    // 識別子のトークンからローカル変数か列挙定数を引く
+    // 内側のスコープから順に探す
+    // 見つからなければエラーを記録して、続きを読めるように0を返す
+    fn lvar(&mut self, t: &Token) -> Node {
+        let ident = match t.kind {
+            TokenType::Ident(ident) => ident,
+            _ => unreachable!(),
+        };
+        for scope in self.scopes.iter().rev() {
+            if let Some(var) = scope.vars.iter().rev().find(|var| var.name == ident) {
+                return Node::with_type(NodeType::LVar(var.offset), var.ty.clone(), t.span);
+            }
+            if let Some(c) = scope.consts.iter().find(|c| c.0 == ident) {
+                return Node::new(NodeType::Num(c.1, Type::Int), t.span);
+            }
+            if scope.typedefs.iter().any(|td| td.0 == ident) {
+                let message = format!("unexpected type name '{}': expected expression", ident);
+                self.report(self.error(t.span, message));
+                return Node::new(NodeType::Num(0, Type::Int), t.span);
+            }
+        }
+        if let Some(ty) = self.globals.get(&ident) {
+            return Node::with_type(NodeType::GVar(ident), ty.clone(), t.span);
+        }
+        let message = format!("use of undeclared identifier '{}'", ident);
+        self.report(Diagnostic::error(E_UNDECLARED, t.span, message));
+        Node::new(NodeType::Num(0, Type::Int), t.span)
+    }
+
+    fn is_typename(&self) -> bool {
+        self.is_type_token(self.peek())
+    }
+
+    fn is_type_token(&self, t: &Token) -> bool {
+        use crate::TokenType::*;
+        match t.kind {
+            Void | Char | Short | Int | Long | Signed | Unsigned | Struct | Union | Enum => true,
+            Ident(name) => self.find_typedef(name).is_some(),
+            _ => false,
+        }
+    }
+
+    // 内側のスコープで変数などに隠されていないtypedef名
+    fn find_typedef(&self, name: Symbol) -> Option<&Type> {
+        for scope in self.scopes.iter().rev() {
+            if scope.vars.iter().any(|var| var.name == name) || scope.consts.iter().any(|c| c.0 == name) {
+                return None;
+            }
+            if let Some(td) = scope.typedefs.iter().rev().find(|td| td.0 == name) {
+                return Some(&td.1);
             }
         }
-        return None;
+        None
     }
 
-    fn expect(&mut self, ty: TokenType) {
-        if let Some(t) = self.tokens.get(self.pos) {
-            if *t == ty {
-                self.pos += 1;
+    // typedef = "typedef" declspec declarator ("," declarator)* ";"
+    fn typedef(&mut self) -> PResult<()> {
+        let base = self.declspec()?;
+        loop {
+            let (ty, t) = self.declarator(base.clone())?;
+            let name = match t.kind {
+                TokenType::Ident(ident) => ident,
+                _ => unreachable!(),
+            };
+            // 同じ型への再定義は許す
+            let scope = self.scopes.last().unwrap();
+            match scope.typedefs.iter().find(|td| td.0 == name) {
+                Some(td) if td.1 == ty => {},
+                Some(td) => {
+                    let message = format!("typedef redefinition with different types ('{}' vs '{}')", ty, td.1);
+                    return Err(Diagnostic::error(E_REDEFINITION, t.span, message));
+                },
+                None => {
+                    self.check_redefinition(name, t.span)?;
+                    self.scopes.last_mut().unwrap().typedefs.push((name, ty));
+                },
+            }
+            if !self.consume(TokenType::Comma) {
+                break;
+            }
+        }
+        self.expect(TokenType::Semicolon)
+    }
+
+    // 末尾はEofトークンなので常に何かしらのトークンを返す
+    fn peek(&self) -> &'a Token {
+        let tokens = self.tokens;
+        &tokens[self.pos.min(tokens.len() - 1)]
+    }
+
+    fn next(&mut self) -> &'a Token {
+        let t = self.peek();
+        self.pos += 1;
+        t
+    }
+
+    fn at_eof(&self) -> bool {
+        self.peek().kind == TokenType::Eof
+    }
+
+    fn error(&self, span: Span, message: String) -> Diagnostic {
+        Diagnostic::error(E_UNEXPECTED_TOKEN, span, message)
+    }
+
+    // 同じ位置の同じエラーは一度だけ記録する
+    fn report(&mut self, e: Diagnostic) {
+        if let Some(last) = self.errors.last() {
+            if last.span == e.span && last.message == e.message {
                 return;
             }
         }
-        panic!("not expect charcter");
+        self.errors.push(e);
     }
 
-    fn consume(&mut self, ty: TokenType) -> bool {
-        if let Some(t) = self.tokens.get(self.pos) {
-            if *t == ty {
-                self.pos += 1;
-                return true;
+    // 文の途中でエラーになったら、次の ";" か閉じ括弧の "}" まで読み飛ばす
+    fn synchronize(&mut self) {
+        let mut depth = 0;
+        loop {
+            match self.peek().kind {
+                TokenType::Eof => return,
+                TokenType::Semicolon if depth == 0 => {
+                    self.pos += 1;
+                    return;
+                },
+                TokenType::LeftBrace => depth += 1,
+                TokenType::RightBrace => {
+                    // ブロックの終わりは呼び出し元で読む
+                    if depth == 0 {
+                        return;
+                    }
+                    depth -= 1;
+                    if depth == 0 {
+                        self.pos += 1;
+                        return;
+                    }
+                },
+                _ => {}
             }
+            self.pos += 1;
+        }
+    }
+
+    // 関数定義の途中でエラーになったら、その関数の終わりか次の関数定義の先頭まで読み飛ばす
+    fn skip_toplevel(&mut self) {
+        let mut depth = 0;
+        loop {
+            let t = self.peek();
+            match t.kind {
+                TokenType::Eof => return,
+                // グローバル変数や型の宣言の終わり
+                TokenType::Semicolon if depth == 0 => {
+                    self.pos += 1;
+                    return;
+                },
+                // 宣言の先頭の型名
+                _ if depth == 0 && (self.is_typename() || t.kind == TokenType::Static || t.kind == TokenType::Typedef) && self.pos > 0 => {
+                    let prev = &self.tokens[self.pos - 1].kind;
+                    if *prev == TokenType::Semicolon || *prev == TokenType::RightBrace {
+                        return;
+                    }
+                },
+                TokenType::LeftBrace => depth += 1,
+                TokenType::RightBrace => {
+                    if depth <= 1 {
+                        self.pos += 1;
+                        return;
+                    }
+                    depth -= 1;
+                },
+                _ => {}
+            }
+            self.pos += 1;
+        }
+    }
+
+    fn expect(&mut self, ty: TokenType) -> PResult<()> {
+        let t = self.peek();
+        if t.kind == ty {
+            self.pos += 1;
+            return Ok(());
+        }
+        Err(self.error(self.expected_span(), format!("expected '{}'", ty)))
+    }
+
+    // 次のトークンが別の行にあるなら、直前のトークンの直後を指す
+    fn expected_span(&self) -> Span {
+        let t = self.peek();
+        if self.pos == 0 {
+            return t.span;
+        }
+        let prev = self.tokens[(self.pos - 1).min(self.tokens.len() - 1)].span;
+        if prev.line == t.span.line {
+            return t.span;
+        }
+        Span {
+            offset: prev.offset + prev.len,
+            len: 1,
+            col: prev.col + prev.len,
+            ..prev
+        }
+    }
+
+    fn consume(&mut self, ty: TokenType) -> bool {
+        if self.peek().kind == ty {
+            self.pos += 1;
+            return true;
         }
         false
     }
 
-    // トップレベルは関数である前提
-    // TODO: グローバル変数とかは無視してる
-    fn toplevel(&mut self) -> NodeType {
-        let t = &self.tokens[self.pos];
-        let name: String;
-        if let TokenType::Ident(ident) = t {
-            name = ident.clone();
-        } else {
-            panic!("not started ident is function")
+    // toplevel = "static"? declspec (function | global_vars)
+    fn toplevel(&mut self) -> PResult<Vec<Node>> {
+        // 関数の中で作ったスコープはエラーで抜けた時も閉じる
+        self.scopes.truncate(1);
+        if self.consume(TokenType::Typedef) {
+            self.typedef()?;
+            return Ok(vec![]);
+        }
+        let is_static = self.consume(TokenType::Static);
+        let base = self.declspec()?;
+        // struct S { ... }; のような型だけの宣言
+        if self.consume(TokenType::Semicolon) {
+            return Ok(vec![]);
+        }
+        let (ty, t) = self.declarator(base.clone())?;
+        if self.peek().kind == TokenType::LeftParen {
+            return Ok(vec![self.function(ty, t, is_static)?]);
+        }
+        self.global_vars(base, ty, t, is_static)
+    }
+
+    // global_vars = ("=" assign)? ("," declarator ("=" assign)?)* ";"
+    // 1つ目の宣言子は呼び出し元で読んである
+    fn global_vars(&mut self, base: Type, mut ty: Type, mut t: &'a Token, is_static: bool) -> PResult<Vec<Node>> {
+        let mut nodes = vec![];
+        loop {
+            let name = match t.kind {
+                TokenType::Ident(ident) => ident,
+                _ => unreachable!(),
+            };
+            if !ty.is_complete() {
+                let message = format!("variable '{}' has incomplete type '{}'", name, ty);
+                return Err(self.error(t.span, message));
+            }
+            self.check_redefinition(name, t.span)?;
+            let mut init = Init::Zero;
+            if self.consume(TokenType::Assign) {
+                init = self.global_init(&ty)?;
+            }
+            self.globals.insert(name, ty.clone());
+            nodes.push(Node::with_type(NodeType::GVarDef(name, init, is_static), ty, t.span));
+
+            if !self.consume(TokenType::Comma) {
+                break;
+            }
+            let (next_ty, next_t) = self.declarator(base.clone())?;
+            ty = next_ty;
+            t = next_t;
+        }
+        self.expect(TokenType::Semicolon)?;
+        Ok(nodes)
+    }
+
+    // グローバル変数の初期値はコンパイル時に決まる値だけ
+    fn global_init(&mut self, ty: &Type) -> PResult<Init> {
+        let node = self.assign()?;
+        if let NodeType::Str(data) = node.kind {
+            self.check_string_init(ty, &data, node.span)?;
+            return Ok(Init::Str(data));
+        }
+        if let Type::Array(..) = ty {
+            return Err(self.error(node.span, "array initializer must be a string literal".into()));
+        }
+        if let Type::Struct(_) = ty {
+            let message = format!("initializing '{}' is not supported for global variables", ty);
+            return Err(Diagnostic::error(E_UNSUPPORTED, node.span, message));
+        }
+        match self.eval(&node) {
+            Some(val) => Ok(Init::Int(val)),
+            None => Err(self.error(node.span, "initializer element is not a compile-time constant".into())),
+        }
+    }
+
+    // 文字列リテラルで初期化できるのはchar配列とcharへのポインタ
+    fn check_string_init(&self, ty: &Type, data: &[u8], span: Span) -> PResult<()> {
+        match ty {
+            Type::Array(base, len) if **base == Type::Char => {
+                // 末尾の\0は入りきらなければ省略できる
+                if data.len() > *len {
+                    return Err(self.error(span, "initializer-string for char array is too long".into()));
+                }
+                Ok(())
+            },
+            Type::Ptr(base) if **base == Type::Char => Ok(()),
+            _ => {
+                let message = format!("cannot initialize a variable of type '{}' with a string literal", ty);
+                Err(self.error(span, message))
+            },
+        }
+    }
+
+    // 定数式を計算する
+    // 変数や関数呼び出しを含む場合はNone
+    fn eval(&self, node: &Node) -> Option<i64> {
+        use self::NodeType::*;
+        let val = match &node.kind {
+            Num(val, _) => *val,
+            Negative(expr) => self.eval(expr)?.wrapping_neg(),
+            Cast(expr) => truncate(self.eval(expr)?, &node.ty),
+            Plus(lhs, rhs) => self.eval(lhs)?.wrapping_add(self.eval(rhs)?),
+            Minus(lhs, rhs) => self.eval(lhs)?.wrapping_sub(self.eval(rhs)?),
+            Mul(lhs, rhs) => self.eval(lhs)?.wrapping_mul(self.eval(rhs)?),
+            // 符号なしの値はu64として計算する
+            Div(lhs, rhs) if node.ty.is_unsigned() => (self.eval(lhs)? as u64).checked_div(self.eval(rhs)? as u64)? as i64,
+            Div(lhs, rhs) => self.eval(lhs)?.checked_div(self.eval(rhs)?)?,
+            Mod(lhs, rhs) if node.ty.is_unsigned() => (self.eval(lhs)? as u64).checked_rem(self.eval(rhs)? as u64)? as i64,
+            Mod(lhs, rhs) => self.eval(lhs)?.checked_rem(self.eval(rhs)?)?,
+            BitAnd(lhs, rhs) => self.eval(lhs)? & self.eval(rhs)?,
+            BitOr(lhs, rhs) => self.eval(lhs)? | self.eval(rhs)?,
+            BitXor(lhs, rhs) => self.eval(lhs)? ^ self.eval(rhs)?,
+            Shl(lhs, rhs) => self.eval(lhs)?.wrapping_shl(self.eval(rhs)? as u32),
+            Shr(lhs, rhs) if node.ty.is_unsigned() => ((self.eval(lhs)? as u64) >> (self.eval(rhs)? & 63)) as i64,
+            Shr(lhs, rhs) => self.eval(lhs)?.wrapping_shr(self.eval(rhs)? as u32),
+            BitNot(expr) => !self.eval(expr)?,
+            Eq(lhs, rhs) => (self.eval(lhs)? == self.eval(rhs)?) as i64,
+            Ne(lhs, rhs) => (self.eval(lhs)? != self.eval(rhs)?) as i64,
+            Lt(lhs, rhs) | Gt(rhs, lhs) if lhs.ty.is_unsigned() => ((self.eval(lhs)? as u64) < self.eval(rhs)? as u64) as i64,
+            Lt(lhs, rhs) | Gt(rhs, lhs) => (self.eval(lhs)? < self.eval(rhs)?) as i64,
+            Le(lhs, rhs) | Ge(rhs, lhs) if lhs.ty.is_unsigned() => (self.eval(lhs)? as u64 <= self.eval(rhs)? as u64) as i64,
+            Le(lhs, rhs) | Ge(rhs, lhs) => (self.eval(lhs)? <= self.eval(rhs)?) as i64,
+            LogAnd(lhs, rhs) => (self.eval(lhs)? != 0 && self.eval(rhs)? != 0) as i64,
+            LogOr(lhs, rhs) => (self.eval(lhs)? != 0 || self.eval(rhs)? != 0) as i64,
+            Not(expr) => (self.eval(expr)? == 0) as i64,
+            Cond(cond, then, els) => if self.eval(cond)? != 0 { self.eval(then)? } else { self.eval(els)? },
+            _ => return None,
+        };
+        Some(val)
+    }
+
+    // function = "(" params? ")" "{" stmt* "}"
+    fn function(&mut self, ret: Type, t: &'a Token, is_static: bool) -> PResult<Node> {
+        let name = match t.kind {
+            TokenType::Ident(ident) => ident,
+            _ => unreachable!(),
+        };
+
+        if let Type::Struct(_) = ret {
+            let message = "returning a struct by value is not supported".to_string();
+            return Err(Diagnostic::error(E_UNSUPPORTED, t.span, message));
+        }
+        self.check_redefinition(name, t.span)?;
+
+        // 関数ごとに新しいフレームを作る
+        // 引数と本体の一番外側のブロックは同じスコープ
+        self.enter_scope();
+        self.offset = 0;
+        self.stacksize = 0;
+
+        self.expect(TokenType::LeftParen)?;
+        let args = self.params()?;
+
+        // 再帰呼び出しできるように本体より先に登録する
+        self.ret = ret.clone();
+        let ty = Type::Func(Box::new(ret));
+        self.funcs.insert(name, ty.clone());
+
+        let brace = self.peek().span;
+        self.expect(TokenType::LeftBrace)?;
+        let body = Box::new(self.compound_stmt(brace)?);
+        self.leave_scope();
+
+        // 一番深くなった時の分をスタックに確保する
+        let stacksize = self.stacksize as usize;
+        Ok(Node::with_type(NodeType::Func(name, args, body, stacksize, is_static), ty, t.span))
+    }
+
+    // params = ("void" | param ("," param)*) ")"
+    // param = declspec declarator
+    fn params(&mut self) -> PResult<Vec<Node>> {
+        let mut args = vec![];
+        if self.consume(TokenType::RightParen) {
+            return Ok(args);
+        }
+        let next = &self.tokens[(self.pos + 1).min(self.tokens.len() - 1)];
+        if self.peek().kind == TokenType::Void && next.kind == TokenType::RightParen {
+            self.pos += 2;
+            return Ok(args);
+        }
+        loop {
+            let base = self.declspec()?;
+            let (mut ty, t) = self.declarator(base)?;
+            // 配列の引数はポインタとして受け取る
+            if let Type::Array(base, _) = ty {
+                ty = base.ptr_to();
+            }
+            if let Type::Struct(_) = ty {
+                let message = "passing a struct by value is not supported".to_string();
+                return Err(Diagnostic::error(E_UNSUPPORTED, t.span, message));
+            }
+            // レジスタで渡せる6個までしか受け取れない
+            if args.len() == 6 {
+                let message = "too many parameters: at most 6 are supported".to_string();
+                return Err(Diagnostic::error(E_UNSUPPORTED, t.span, message));
+            }
+            if let TokenType::Ident(ident) = t.kind {
+                args.push(self.declare(ident, ty, t.span)?);
+            }
+            if !self.consume(TokenType::Comma) {
+                break;
+            }
+        }
+        self.expect(TokenType::RightParen)?;
+        Ok(args)
+    }
+
+    // declspec = ("void" | "char" | "short" | "int" | "long")+
+    //          | ("struct" | "union") struct_decl
+    //          | "enum" enum_decl
+    //          | typedef_name
+    // short int, long long などの組み合わせも受け付ける
+    fn declspec(&mut self) -> PResult<Type> {
+        let span = self.peek().span;
+        if !self.is_typename() {
+            return Err(self.error(span, "expected type name".into()));
+        }
+        if self.consume(TokenType::Struct) {
+            return self.struct_decl(false);
+        }
+        if self.consume(TokenType::Union) {
+            return self.struct_decl(true);
+        }
+        if self.consume(TokenType::Enum) {
+            return self.enum_decl();
+        }
+        if let TokenType::Ident(name) = self.peek().kind {
+            self.pos += 1;
+            return Ok(self.find_typedef(name).unwrap().clone());
+        }
+        let mut counts = [0; 7];
+        while self.is_typename() {
+            // int T; のTは宣言子なので、typedef名でも型としては読まない
+            if let TokenType::Ident(_) = self.peek().kind {
+                break;
+            }
+            let t = self.next();
+            let i = match t.kind {
+                TokenType::Void => 0,
+                TokenType::Char => 1,
+                TokenType::Short => 2,
+                TokenType::Int => 3,
+                TokenType::Long => 4,
+                TokenType::Signed => 5,
+                TokenType::Unsigned => 6,
+                _ => {
+                    self.pos -= 1;
+                    return Err(self.error(t.span, "two or more data types in declaration specifiers".into()));
+                },
+            };
+            counts[i] += 1;
+        }
+        // signedとunsignedは片方だけ1回まで書ける
+        let ty = match counts {
+            [1, 0, 0, 0, 0, 0, 0] => Type::Void,
+            [0, 1, 0, 0, 0, 0 | 1, 0] => Type::Char,
+            [0, 1, 0, 0, 0, 0, 1] => Type::UChar,
+            [0, 0, 1, 0 | 1, 0, 0 | 1, 0] => Type::Short,
+            [0, 0, 1, 0 | 1, 0, 0, 1] => Type::UShort,
+            [0, 0, 0, 0 | 1, 0, 0 | 1, 0] if counts[3] + counts[5] > 0 => Type::Int,
+            [0, 0, 0, 0 | 1, 0, 0, 1] => Type::UInt,
+            [0, 0, 0, 0 | 1, 1 | 2, 0 | 1, 0] => Type::Long,
+            [0, 0, 0, 0 | 1, 1 | 2, 0, 1] => Type::ULong,
+            _ => return Err(self.error(span, "two or more data types in declaration specifiers".into())),
+        };
+        Ok(ty)
+    }
+
+    // struct_decl = ident? ("{" struct_member* "}")?
+    fn struct_decl(&mut self, is_union: bool) -> PResult<Type> {
+        let t = self.peek();
+        let tag = match t.kind {
+            TokenType::Ident(ident) => {
+                self.pos += 1;
+                Some(ident)
+            },
+            _ => None,
+        };
+
+        if self.peek().kind != TokenType::LeftBrace {
+            let tag = match tag {
+                Some(tag) => tag,
+                None => return Err(self.error(self.peek().span, "expected '{'".into())),
+            };
+            if let Some(record) = self.find_tag(tag) {
+                return Ok(Type::Struct(record.clone()));
+            }
+            // 前方宣言: メンバーは後で定義される
+            let record = Record::new(Some(tag), is_union);
+            self.scopes.last_mut().unwrap().tags.push((tag, record.clone()));
+            return Ok(Type::Struct(record));
         }
         self.pos += 1;
 
-        // TODO: 一旦引数無しで定義できるように
-        self.expect(TokenType::LeftParen);
-        let args = vec![];
-        self.expect(TokenType::RightParen);
+        // 同じスコープで前方宣言されていれば、その型にメンバーを入れる
+        // 再定義はエラーを記録して、メンバーは読んでおく
+        let mut record = None;
+        if let Some(tag) = tag {
+            if let Some(r) = self.scopes.last().unwrap().tags.iter().find(|r| r.0 == tag).map(|r| r.1.clone()) {
+                if r.0.borrow().complete {
+                    let message = format!("redefinition of '{}'", Type::Struct(r.clone()));
+                    self.report(Diagnostic::error(E_REDEFINITION, t.span, message));
+                    record = Some(Record::new(Some(tag), is_union));
+                } else {
+                    record = Some(r);
+                }
+            }
+        }
+        let record = match record {
+            Some(record) => record,
+            None => {
+                let record = Record::new(tag, is_union);
+                if let Some(tag) = tag {
+                    self.scopes.last_mut().unwrap().tags.push((tag, record.clone()));
+                }
+                record
+            },
+        };
 
-        self.expect(TokenType::LeftBrace);
-        let body = Box::new(self.compound_stmt());
+        // エラーになったメンバーは読み飛ばして続ける
+        let mut members = vec![];
+        while !self.consume(TokenType::RightBrace) {
+            if self.at_eof() {
+                return Err(self.error(self.expected_span(), "expected '}'".into()));
+            }
+            if let Err(e) = self.struct_member(&mut members) {
+                self.report(e);
+                self.synchronize();
+            }
+        }
+        record.set_members(members);
+        self.records.push(record.clone());
+        Ok(Type::Struct(record))
+    }
+
+    // enum_decl = ident? ("{" enumerator ("," enumerator)* ","? "}")?
+    // enumerator = ident ("=" assign)?
+    // 列挙型はintとして扱う
+    fn enum_decl(&mut self) -> PResult<Type> {
+        let t = self.peek().clone();
+        let tag = match t.kind {
+            TokenType::Ident(ident) => {
+                self.pos += 1;
+                Some(ident)
+            },
+            _ => None,
+        };
+        if !self.consume(TokenType::LeftBrace) {
+            let tag = match tag {
+                Some(tag) => tag,
+                None => return Err(self.error(self.peek().span, "expected '{'".into())),
+            };
+            // 構造体と違って前方宣言はできない
+            if !self.scopes.iter().any(|scope| scope.enums.contains(&tag)) {
+                let message = format!("use of undeclared enum '{}'", tag);
+                return Err(Diagnostic::error(E_UNDECLARED, t.span, message));
+            }
+            return Ok(Type::Int);
+        }
+        if let Some(tag) = tag {
+            if self.scopes.last().unwrap().enums.contains(&tag) {
+                let message = format!("redefinition of 'enum {}'", tag);
+                self.report(Diagnostic::error(E_REDEFINITION, t.span, message));
+            } else {
+                self.scopes.last_mut().unwrap().enums.push(tag);
+            }
+        }
 
-        // TODO: 引数無しなのでスタックサイズも0
-        NodeType::Func(name, args, body, 0)
+        // 値を省略したら直前の値 + 1
+        // 値や名前のエラーは記録だけして続きを読む
+        let mut val = 0;
+        while !self.consume(TokenType::RightBrace) {
+            let t = self.next();
+            let name = match t.kind {
+                TokenType::Ident(ident) => ident,
+                _ => {
+                    self.pos -= 1;
+                    return Err(self.error(t.span, "expected identifier".into()));
+                }
+            };
+            // 列挙定数はintなので、intに収まらない値はエラー
+            if self.consume(TokenType::Assign) {
+                let span = self.peek().span;
+                match self.const_expr() {
+                    Ok(v) if truncate(v, &Type::Int) == v => val = v,
+                    Ok(_) => self.report(self.error(span, "enumerator value is not representable in 'int'".into())),
+                    Err(e) => self.report(e),
+                }
+            } else if val > i32::MAX as i64 {
+                self.report(self.error(t.span, "overflow in enumeration value".into()));
+            }
+            match self.check_redefinition(name, t.span) {
+                Ok(()) => self.scopes.last_mut().unwrap().consts.push((name, val)),
+                Err(e) => self.report(e),
+            }
+            val += 1;
+            if !self.consume(TokenType::Comma) {
+                self.expect(TokenType::RightBrace)?;
+                break;
+            }
+        }
+        Ok(Type::Int)
+    }
+
+    fn const_expr(&mut self) -> PResult<i64> {
+        let node = self.conditional()?;
+        match self.eval(&node) {
+            Some(val) => Ok(val),
+            None => Err(self.error(node.span, "expression is not an integer constant expression".into())),
+        }
+    }
+
+    // struct_member = declspec (declarator ("," declarator)*)? ";"
+    // 宣言子のない構造体・共用体は無名のメンバーになる
+    fn struct_member(&mut self, members: &mut Vec<Member>) -> PResult<()> {
+        let base = self.declspec()?;
+        if let Type::Struct(_) = base {
+            if self.consume(TokenType::Semicolon) {
+                members.push(Member { name: None, ty: base, offset: 0 });
+                return Ok(());
+            }
+        }
+        loop {
+            let (ty, t) = self.declarator(base.clone())?;
+            let name = match t.kind {
+                TokenType::Ident(ident) => ident,
+                _ => unreachable!(),
+            };
+            if !ty.is_complete() {
+                let message = format!("field '{}' has incomplete type '{}'", name, ty);
+                return Err(self.error(t.span, message));
+            }
+            if members.iter().any(|m| m.name == Some(name)) {
+                let message = format!("duplicate member '{}'", name);
+                return Err(Diagnostic::error(E_REDEFINITION, t.span, message));
+            }
+            members.push(Member { name: Some(name), ty, offset: 0 });
+            if !self.consume(TokenType::Comma) {
+                break;
+            }
+        }
+        self.expect(TokenType::Semicolon)
+    }
+
+    // declarator = "*"* ident type_suffix
+    fn declarator(&mut self, mut ty: Type) -> PResult<(Type, &'a Token)> {
+        while self.consume(TokenType::Mul) {
+            ty = ty.ptr_to();
+        }
+        let t = self.next();
+        match t.kind {
+            TokenType::Ident(_) => Ok((self.type_suffix(ty)?, t)),
+            _ => {
+                self.pos -= 1;
+                Err(self.error(t.span, "expected identifier".into()))
+            }
+        }
+    }
+
+    // type_name = declspec "*"* type_suffix
+    // sizeof(int *) のような名前のない型
+    fn type_name(&mut self) -> PResult<Type> {
+        let mut ty = self.declspec()?;
+        while self.consume(TokenType::Mul) {
+            ty = ty.ptr_to();
+        }
+        self.type_suffix(ty)
+    }
+
+    // type_suffix = ("[" const_expr "]" type_suffix)?
+    // int a[2][3] は「int[3]を2個並べた配列」
+    fn type_suffix(&mut self, ty: Type) -> PResult<Type> {
+        if !self.consume(TokenType::LeftBracket) {
+            return Ok(ty);
+        }
+        let span = self.peek().span;
+        let len = match self.const_expr()? {
+            val if val > 0 => val as usize,
+            _ => return Err(self.error(span, "array size must be positive".into())),
+        };
+        self.expect(TokenType::RightBracket)?;
+        let ty = self.type_suffix(ty)?;
+        // sizeofの値はlongに収まらなければならない
+        match ty.size().checked_mul(len) {
+            Some(size) if size <= i64::MAX as usize => Ok(Type::Array(Box::new(ty), len)),
+            _ => Err(self.error(span, "array is too large".into())),
+        }
+    }
+
+    // declaration = declspec (declarator ("=" assign)? ("," declarator ("=" assign)?)*)? ";"
+    // 初期化式は代入文の並びにする
+    fn declaration(&mut self) -> PResult<Node> {
+        let span = self.peek().span;
+        let mut inits = vec![];
+        if self.consume(TokenType::Typedef) {
+            self.typedef()?;
+            return Ok(Node::new(NodeType::Block(inits), span));
+        }
+        let base = self.declspec()?;
+        if self.consume(TokenType::Semicolon) {
+            return Ok(Node::new(NodeType::Block(inits), span));
+        }
+        loop {
+            let (ty, t) = self.declarator(base.clone())?;
+            let name = match t.kind {
+                TokenType::Ident(ident) => ident,
+                _ => unreachable!(),
+            };
+            let var = self.declare(name, ty, t.span)?;
+            let assign = self.peek().span;
+            if self.consume(TokenType::Assign) {
+                let rhs = self.assign()?;
+                if let Type::Array(..) = var.ty {
+                    inits.extend(self.array_init(var, rhs)?);
+                } else {
+                    self.check_assign(&var, &rhs, assign)?;
+                    let init = new_assign(var, rhs, assign);
+                    inits.push(Node::new(NodeType::ExprStmt(Box::new(init)), t.span));
+                }
+            }
+            if !self.consume(TokenType::Comma) {
+                break;
+            }
+        }
+        self.expect(TokenType::Semicolon)?;
+        Ok(Node::new(NodeType::Block(inits), span))
+    }
+
+    // char配列を文字列リテラルで初期化する
+    // 1文字ずつ代入し、残りは0で埋める
+    fn array_init(&self, var: Node, rhs: Node) -> PResult<Vec<Node>> {
+        let data = match rhs.kind {
+            NodeType::Str(ref data) => data,
+            _ => return Err(self.error(rhs.span, "array initializer must be a string literal".into())),
+        };
+        self.check_string_init(&var.ty, data, rhs.span)?;
+        let mut inits = vec![];
+        for i in 0..var.ty.size() {
+            let span = rhs.span;
+            let index = Node::new(NodeType::Num(i as i64, Type::Int), span);
+            let elem = self.new_deref(self.new_add(var.clone(), index, span)?, span)?;
+            let c = Node::new(NodeType::Num(data.get(i).copied().unwrap_or(0) as i64, Type::Int), span);
+            let init = new_assign(elem, c, span);
+            inits.push(Node::new(NodeType::ExprStmt(Box::new(init)), span));
+        }
+        Ok(inits)
     }
 
     // coumpound_stmt = stmt*
-    fn compound_stmt(&mut self) -> NodeType {
+    fn compound_stmt(&mut self, span: Span) -> PResult<Node> {
+        let stmts = self.stmts()?;
+        Ok(Node::new(NodeType::CompStmt(stmts), span))
+    }
+
+    // "}" までの文を読む
+    // エラーになった文は記録して読み飛ばし、次の文から続ける
+    fn stmts(&mut self) -> PResult<Vec<Node>> {
         let mut stmts = vec![];
         while !self.consume(TokenType::RightBrace) {
-            stmts.push(self.stmt());
+            if self.at_eof() {
+                return Err(self.error(self.expected_span(), "expected '}'".into()));
+            }
+            match self.stmt() {
+                Ok(stmt) => stmts.push(stmt),
+                Err(e) => {
+                    self.report(e);
+                    self.synchronize();
+                }
+            }
         }
-        NodeType::CompStmt(stmts)
+        Ok(stmts)
     }
 
     // stmt =  expr_stmt ";"
+    //      | ";"
+    //      | declaration
     //      | "{" stmt* "}"
     //      | "if" "(" expr ")" stmt ("else" stmt)?
     //      | "while" "(" expr ")" stmt
-    //      | "for" "(" expr? ";" expr? ";" expr? ")" stmt
-    //      | "return" expr ";"
-    fn stmt(&mut self) -> NodeType {
-        let t = &self.tokens[self.pos];
-        self.pos += 1;
-        match t {
+    //      | "for" for_stmt
+    //      | "return" expr? ";"
+    fn stmt(&mut self) -> PResult<Node> {
+        let t = self.next();
+        let kind = match t.kind {
             TokenType::If => {
-                self.expect(TokenType::LeftParen);
-                let expr = Box::new(self.expr());
-                self.expect(TokenType::RightParen);
-                let stmt = Box::new(self.stmt());
+                self.expect(TokenType::LeftParen)?;
+                let expr = Box::new(self.expr()?);
+                self.expect(TokenType::RightParen)?;
+                let stmt = Box::new(self.stmt()?);
                 let mut else_stmt = None;
                 if self.consume(TokenType::Else) {
-                    else_stmt = Some(Box::new(self.stmt()));
+                    else_stmt = Some(Box::new(self.stmt()?));
                 }
                 NodeType::If(expr, stmt, else_stmt)
             },
             TokenType::For => {
-                self.expect(TokenType::LeftParen);
-
-                let mut init = None;
-                if !self.consume(TokenType::Semicolon) {
-                    init = Some(Box::new(self.expr()));
-                    self.expect(TokenType::Semicolon);
-                }
-
-                let mut cond = None;
-                if !self.consume(TokenType::Semicolon) {
-                    cond = Some(Box::new(self.expr()));
-                    self.expect(TokenType::Semicolon);
-                }
-
-                let mut inc = None;
-                if !self.consume(TokenType::RightParen) {
-                    inc = Some(Box::new(self.expr()));
-                    self.expect(TokenType::RightParen);
-                }
-
-                let stmt = Box::new(self.stmt());
-                NodeType::For(init, cond, inc, stmt)
+                // 初期化部で宣言した変数はfor文の中だけで使える
+                self.enter_scope();
+                let kind = self.for_stmt();
+                self.leave_scope();
+                kind?
             },
             TokenType::While => {
-                self.expect(TokenType::LeftParen);
-                let expr = Box::new(self.expr());
-                self.expect(TokenType::RightParen);
-                let stmt = Box::new(self.stmt());
+                self.expect(TokenType::LeftParen)?;
+                let expr = Box::new(self.expr()?);
+                self.expect(TokenType::RightParen)?;
+                let stmt = Box::new(self.stmt()?);
                 // TODO: 参考ソースでは、条件のみ指定したfor文としてノードを作っている
                 NodeType::While(expr, stmt)
             },
             TokenType::Return => {
-                let expr = self.expr();
-                self.expect(TokenType::Semicolon);
+                // voidの関数では値のないreturnを書ける。戻り値は使われないので0にしておく
+                if self.ret == Type::Void && self.peek().kind == TokenType::Semicolon {
+                    self.pos += 1;
+                    let zero = Node::new(NodeType::Num(0, Type::Int), t.span);
+                    return Ok(Node::new(NodeType::Return(Box::new(zero)), t.span));
+                }
+                let mut expr = self.expr()?;
+                self.expect(TokenType::Semicolon)?;
+                if self.ret.is_integer() && expr.ty.is_integer() {
+                    expr = new_cast(expr, self.ret.clone());
+                }
                 NodeType::Return(Box::new(expr))
             },
+            // for (...) ; のような空の文
+            TokenType::Semicolon => NodeType::Block(vec![]),
             TokenType::LeftBrace => {
-                let mut stmts = vec![];
-                while !self.consume(TokenType::RightBrace) {
-                    stmts.push(self.stmt());
-                }
-                NodeType::Block(stmts)
-            }
+                self.enter_scope();
+                let stmts = self.stmts();
+                self.leave_scope();
+                NodeType::Block(stmts?)
+            },
             _ => {
                 // 代入式などがあるので必要
                 self.pos -= 1;
-                self.expr_stmt()
+                // 型名で始まれば宣言、T * x; もTがtypedef名なら宣言になる
+                if self.is_typename() || self.peek().kind == TokenType::Typedef {
+                    return self.declaration();
+                }
+                return self.expr_stmt();
             }
+        };
+        Ok(Node::new(kind, t.span))
+    }
+
+    // for_stmt = "(" (declaration | expr? ";") expr? ";" expr? ")" stmt
+    fn for_stmt(&mut self) -> PResult<NodeType> {
+        self.expect(TokenType::LeftParen)?;
+
+        let mut init = None;
+        if self.is_typename() {
+            init = Some(Box::new(self.declaration()?));
+        } else if !self.consume(TokenType::Semicolon) {
+            init = Some(Box::new(self.expr()?));
+            self.expect(TokenType::Semicolon)?;
+        }
+
+        let mut cond = None;
+        if !self.consume(TokenType::Semicolon) {
+            cond = Some(Box::new(self.expr()?));
+            self.expect(TokenType::Semicolon)?;
+        }
+
+        let mut inc = None;
+        if !self.consume(TokenType::RightParen) {
+            inc = Some(Box::new(self.expr()?));
+            self.expect(TokenType::RightParen)?;
         }
+
+        let stmt = Box::new(self.stmt()?);
+        Ok(NodeType::For(init, cond, inc, stmt))
     }
 
     // expr_stmt = expr ";"
-    fn expr_stmt(&mut self) -> NodeType {
-        let expr = self.expr();
-        self.expect(TokenType::Semicolon);
-        NodeType::ExprStmt(Box::new(expr))
+    fn expr_stmt(&mut self) -> PResult<Node> {
+        let expr = self.expr()?;
+        let span = expr.span;
+        self.expect(TokenType::Semicolon)?;
+        Ok(Node::new(NodeType::ExprStmt(Box::new(expr)), span))
+    }
+
+    // expr = assign ("," assign)*
+    fn expr(&mut self) -> PResult<Node> {
+        let mut assign = self.assign()?;
+        loop {
+            let span = self.peek().span;
+            if !self.consume(TokenType::Comma) {
+                break;
+            }
+            let rhs = self.assign()?;
+            assign = Node::new(NodeType::Comma(Box::new(assign), Box::new(rhs)), span);
+        }
+        Ok(assign)
+    }
+
+    // assign = conditional (assign_op assign)?
+    // assign_op = "=" | "+=" | "-=" | "*=" | "/=" | "%=" | "&=" | "|=" | "^=" | "<<=" | ">>="
+    fn assign(&mut self) -> PResult<Node> {
+        let eq = self.conditional()?;
+        let t = self.peek();
+        let op = match t.kind {
+            TokenType::Assign => None,
+            TokenType::AddAssign => Some(TokenType::Plus),
+            TokenType::SubAssign => Some(TokenType::Minus),
+            TokenType::MulAssign => Some(TokenType::Mul),
+            TokenType::DivAssign => Some(TokenType::Div),
+            TokenType::ModAssign => Some(TokenType::Mod),
+            TokenType::AndAssign => Some(TokenType::BitAnd),
+            TokenType::OrAssign => Some(TokenType::BitOr),
+            TokenType::XorAssign => Some(TokenType::BitXor),
+            TokenType::ShlAssign => Some(TokenType::Shl),
+            TokenType::ShrAssign => Some(TokenType::Shr),
+            _ => return Ok(eq),
+        };
+        self.pos += 1;
+        if let Type::Array(..) = eq.ty {
+            let message = format!("array type '{}' is not assignable", eq.ty);
+            return Err(self.error(t.span, message));
+        }
+        let assign = self.assign()?;
+        match op {
+            Some(op) => self.new_op_assign(op, eq, assign, t.span),
+            None => {
+                self.check_assign(&eq, &assign, t.span)?;
+                Ok(new_assign(eq, assign, t.span))
+            },
+        }
+    }
+
+    // 構造体は同じ型どうしでだけ代入できる
+    fn check_assign(&self, lhs: &Node, rhs: &Node, span: Span) -> PResult<()> {
+        let ok = match (&lhs.ty, &rhs.ty) {
+            (Type::Struct(_), _) | (_, Type::Struct(_)) => lhs.ty == rhs.ty,
+            (l, r) => is_scalar(l) && is_scalar(r),
+        };
+        if !ok {
+            let message = format!("assigning to '{}' from incompatible type '{}'", lhs.ty, rhs.ty);
+            return Err(self.error(span, message));
+        }
+        Ok(())
+    }
+
+    // a op= b は、tmp = &a, *tmp = *tmp op b にしてaのアドレスを一度だけ計算する
+    // 変数なら副作用がないので a = a op b のままでよい
+    fn new_op_assign(&mut self, op: TokenType, lhs: Node, rhs: Node, span: Span) -> PResult<Node> {
+        if let NodeType::LVar(_) | NodeType::GVar(_) = lhs.kind {
+            let value = self.new_binary(op, lhs.clone(), rhs, span)?;
+            return Ok(new_assign(lhs, value, span));
+        }
+        let ptr = lhs.ty.clone().ptr_to();
+        let tmp = self.new_temp(ptr, span)?;
+        let addr = Node::new(NodeType::Addr(Box::new(lhs)), span);
+        let set = new_assign(tmp.clone(), addr, span);
+        let value = Node::new(NodeType::Deref(Box::new(tmp.clone())), span);
+        let value = self.new_binary(op, value, rhs, span)?;
+        let assign = new_assign(Node::new(NodeType::Deref(Box::new(tmp)), span), value, span);
+        Ok(Node::new(NodeType::Comma(Box::new(set), Box::new(assign)), span))
+    }
+
+    // 二項演算子のトークンに合わせて式を作る
+    fn new_binary(&self, op: TokenType, lhs: Node, rhs: Node, span: Span) -> PResult<Node> {
+        match op {
+            TokenType::Plus => self.new_add(lhs, rhs, span),
+            TokenType::Minus => self.new_sub(lhs, rhs, span),
+            TokenType::Mul => self.new_arith(NodeType::Mul, lhs, rhs, span),
+            TokenType::Div => self.new_arith(NodeType::Div, lhs, rhs, span),
+            TokenType::Mod => self.new_arith(NodeType::Mod, lhs, rhs, span),
+            TokenType::BitAnd => self.new_arith(NodeType::BitAnd, lhs, rhs, span),
+            TokenType::BitOr => self.new_arith(NodeType::BitOr, lhs, rhs, span),
+            TokenType::BitXor => self.new_arith(NodeType::BitXor, lhs, rhs, span),
+            TokenType::Shl => self.new_shift(NodeType::Shl, lhs, rhs, span),
+            TokenType::Shr => self.new_shift(NodeType::Shr, lhs, rhs, span),
+            _ => unreachable!(),
+        }
     }
 
-    // expr = assign
-    fn expr(&mut self) -> NodeType {
-        self.assign()
+    // a++ は (a += 1) - 1 を元の型に戻したもの
+    fn new_inc_dec(&mut self, node: Node, is_inc: bool, is_post: bool, span: Span) -> PResult<Node> {
+        if !is_scalar(&node.ty) || matches!(node.ty, Type::Array(..)) {
+            let op = if is_inc { "increment" } else { "decrement" };
+            let message = format!("cannot {} value of type '{}'", op, node.ty);
+            return Err(self.error(span, message));
+        }
+        let ty = node.ty.clone();
+        let (op, back) = if is_inc { (TokenType::Plus, TokenType::Minus) } else { (TokenType::Minus, TokenType::Plus) };
+        let one = || Node::new(NodeType::Num(1, Type::Int), span);
+        let assign = self.new_op_assign(op, node, one(), span)?;
+        if !is_post {
+            return Ok(assign);
+        }
+        let value = self.new_binary(back, assign, one(), span)?;
+        Ok(new_cast(value, ty))
     }
 
-    // assign = equality ("=" assign)?
-    fn assign(&mut self) -> NodeType {
-        let eq = self.equality();
-        if self.consume(TokenType::Assign) {
-            let assign = Box::new(self.assign());
-            NodeType::Assign(Box::new(eq), assign)
+    // conditional = logor ("?" expr ":" conditional)?
+    fn conditional(&mut self) -> PResult<Node> {
+        let cond = self.logor()?;
+        let span = self.peek().span;
+        if !self.consume(TokenType::Question) {
+            return Ok(cond);
+        }
+        let then = decay(self.expr()?);
+        self.expect(TokenType::Colon)?;
+        let els = decay(self.conditional()?);
+        if !is_scalar(&cond.ty) {
+            let message = format!("used type '{}' where arithmetic or pointer type is required", cond.ty);
+            return Err(self.error(cond.span, message));
+        }
+        // 両方整数なら通常の算術変換、それ以外は同じ型か片方が0のポインタ
+        let ty = if then.ty.is_integer() && els.ty.is_integer() {
+            Type::arith(&then.ty, &els.ty)
+        } else if then.ty == els.ty || (then.ty.base().is_some() && is_null(&els)) {
+            then.ty.clone()
+        } else if els.ty.base().is_some() && is_null(&then) {
+            els.ty.clone()
         } else {
-            eq
+            let message = format!("incompatible operand types ('{}' and '{}')", then.ty, els.ty);
+            return Err(self.error(span, message));
+        };
+        let (then, els) = if ty.is_integer() { (new_cast(then, ty.clone()), new_cast(els, ty.clone())) } else { (then, els) };
+        Ok(Node::with_type(NodeType::Cond(Box::new(cond), Box::new(then), Box::new(els)), ty, span))
+    }
+
+    // logor = logand ("||" logand)*
+    fn logor(&mut self) -> PResult<Node> {
+        let mut logand = self.logand()?;
+        loop {
+            let span = self.peek().span;
+            if !self.consume(TokenType::LogOr) {
+                break;
+            }
+            let rhs = self.logand()?;
+            self.check_scalar(&logand, &rhs, span)?;
+            logand = Node::new(NodeType::LogOr(Box::new(logand), Box::new(rhs)), span);
         }
+        Ok(logand)
+    }
+
+    // logand = bitor ("&&" bitor)*
+    fn logand(&mut self) -> PResult<Node> {
+        let mut bitor = self.bitor()?;
+        loop {
+            let span = self.peek().span;
+            if !self.consume(TokenType::LogAnd) {
+                break;
+            }
+            let rhs = self.bitor()?;
+            self.check_scalar(&bitor, &rhs, span)?;
+            bitor = Node::new(NodeType::LogAnd(Box::new(bitor), Box::new(rhs)), span);
+        }
+        Ok(bitor)
+    }
+
+    // bitor = bitxor ("|" bitxor)*
+    fn bitor(&mut self) -> PResult<Node> {
+        let mut bitxor = self.bitxor()?;
+        loop {
+            let span = self.peek().span;
+            if !self.consume(TokenType::BitOr) {
+                break;
+            }
+            let rhs = self.bitxor()?;
+            bitxor = self.new_arith(NodeType::BitOr, bitxor, rhs, span)?;
+        }
+        Ok(bitxor)
+    }
+
+    // bitxor = bitand ("^" bitand)*
+    fn bitxor(&mut self) -> PResult<Node> {
+        let mut bitand = self.bitand()?;
+        loop {
+            let span = self.peek().span;
+            if !self.consume(TokenType::BitXor) {
+                break;
+            }
+            let rhs = self.bitand()?;
+            bitand = self.new_arith(NodeType::BitXor, bitand, rhs, span)?;
+        }
+        Ok(bitand)
+    }
+
+    // bitand = equality ("&" equality)*
+    fn bitand(&mut self) -> PResult<Node> {
+        let mut eq = self.equality()?;
+        loop {
+            let span = self.peek().span;
+            if !self.consume(TokenType::BitAnd) {
+                break;
+            }
+            let rhs = self.equality()?;
+            eq = self.new_arith(NodeType::BitAnd, eq, rhs, span)?;
+        }
+        Ok(eq)
+    }
+
+    // 真偽値として使えるのは整数とポインタ
+    fn check_scalar(&self, lhs: &Node, rhs: &Node, span: Span) -> PResult<()> {
+        if !is_scalar(&lhs.ty) || !is_scalar(&rhs.ty) {
+            return Err(self.invalid_operands(lhs, rhs, span));
+        }
+        Ok(())
     }
 
     // equality = relational ("==" relational | "!=" relational)*
-    fn equality(&mut self) -> NodeType {
-        let mut rel = self.relational();
+    fn equality(&mut self) -> PResult<Node> {
+        let mut rel = self.relational()?;
         loop {
+            let span = self.peek().span;
             if self.consume(TokenType::Eq) {
-                let rhs = Box::new(self.relational());
-                rel = NodeType::Eq(Box::new(rel), rhs);
+                let rhs = self.relational()?;
+                rel = self.new_cmp(NodeType::Eq, rel, rhs, span)?;
                 continue;
             } else if self.consume(TokenType::Ne) {
-                let rhs = Box::new(self.relational());
-                rel = NodeType::Ne(Box::new(rel), rhs);
+                let rhs = self.relational()?;
+                rel = self.new_cmp(NodeType::Ne, rel, rhs, span)?;
                 continue;
             }
             break;
         }
-        rel
+        Ok(rel)
     }
 
-    // relational = add ("<" add | "<=" add | ">" add | ">=" add)*
-    fn relational(&mut self) -> NodeType {
-        let mut add = self.add();
+    // relational = shift ("<" shift | "<=" shift | ">" shift | ">=" shift)*
+    fn relational(&mut self) -> PResult<Node> {
+        let mut add = self.shift()?;
         loop {
+            let span = self.peek().span;
             if self.consume(TokenType::Lt) {
-                let rhs = Box::new(self.add());
-                add = NodeType::Lt(Box::new(add), rhs);
+                let rhs = self.shift()?;
+                add = self.new_cmp(NodeType::Lt, add, rhs, span)?;
                 continue;
             } else if self.consume(TokenType::Le) {
-                let rhs = Box::new(self.add());
-                add = NodeType::Le(Box::new(add), rhs);
+                let rhs = self.shift()?;
+                add = self.new_cmp(NodeType::Le, add, rhs, span)?;
                 continue;
             } else if self.consume(TokenType::Gt) {
-                let rhs = Box::new(self.add());
-                add = NodeType::Gt(Box::new(add), rhs);
+                let rhs = self.shift()?;
+                add = self.new_cmp(NodeType::Gt, add, rhs, span)?;
                 continue;
             } else if self.consume(TokenType::Ge) {
-                let rhs = Box::new(self.add());
-                add = NodeType::Ge(Box::new(add), rhs);
+                let rhs = self.shift()?;
+                add = self.new_cmp(NodeType::Ge, add, rhs, span)?;
+                continue;
+            }
+            break;
+        }
+        Ok(add)
+    }
+
+    // shift = add ("<<" add | ">>" add)*
+    fn shift(&mut self) -> PResult<Node> {
+        let mut add = self.add()?;
+        loop {
+            let span = self.peek().span;
+            if self.consume(TokenType::Shl) {
+                let rhs = self.add()?;
+                add = self.new_shift(NodeType::Shl, add, rhs, span)?;
+                continue;
+            } else if self.consume(TokenType::Shr) {
+                let rhs = self.add()?;
+                add = self.new_shift(NodeType::Shr, add, rhs, span)?;
                 continue;
             }
             break;
         }
-        add
+        Ok(add)
+    }
+
+    // シフトの結果は左辺を拡張した型で、右辺の型には揃えない
+    fn new_shift(&self, kind: fn(Box<Node>, Box<Node>) -> NodeType, lhs: Node, rhs: Node, span: Span) -> PResult<Node> {
+        if !lhs.ty.is_integer() || !rhs.ty.is_integer() {
+            return Err(self.invalid_operands(&lhs, &rhs, span));
+        }
+        let ty = lhs.ty.promote();
+        let wide = widen(&ty);
+        let rhs_ty = widen(&rhs.ty);
+        let (lhs, rhs) = (new_cast(lhs, wide.clone()), new_cast(rhs, rhs_ty));
+        let node = Node::with_type(kind(Box::new(lhs), Box::new(rhs)), wide, span);
+        Ok(new_cast(node, ty))
     }
 
     // add = mul ("+" mul | "-" mul)*
-    fn add(&mut self) -> NodeType {
-        let mut mul = self.mul();
+    fn add(&mut self) -> PResult<Node> {
+        let mut mul = self.mul()?;
         loop {
+            let span = self.peek().span;
             if self.consume(TokenType::Plus) {
-                let rhs = Box::new(self.mul());
-                mul = NodeType::Plus(Box::new(mul), rhs);
+                let rhs = self.mul()?;
+                mul = self.new_add(mul, rhs, span)?;
                 continue;
             } else if self.consume(TokenType::Minus) {
-                let rhs = Box::new(self.mul());
-                mul = NodeType::Minus(Box::new(mul), rhs);
+                let rhs = self.mul()?;
+                mul = self.new_sub(mul, rhs, span)?;
                 continue;
             }
             break;
         }
-        mul
+        Ok(mul)
+    }
+
+    fn invalid_operands(&self, lhs: &Node, rhs: &Node, span: Span) -> Diagnostic {
+        let message = format!("invalid operands to binary expression ('{}' and '{}')", lhs.ty, rhs.ty);
+        self.error(span, message)
+    }
+
+    // ポインタに足す整数は指す先の大きさ倍にする
+    fn scale(node: Node, ptr: &Type) -> Node {
+        let size = ptr.base().unwrap().size();
+        let span = node.span;
+        let size = Node::new(NodeType::Num(size as i64, Type::Long), span);
+        Node::new(NodeType::Mul(Box::new(node), Box::new(size)), span)
+    }
+
+    fn new_add(&self, lhs: Node, rhs: Node, span: Span) -> PResult<Node> {
+        let kind = match (lhs.ty.base(), rhs.ty.base()) {
+            (Some(_), Some(_)) => return Err(self.invalid_operands(&lhs, &rhs, span)),
+            (Some(_), _) => {
+                let rhs = Self::scale(rhs, &lhs.ty);
+                NodeType::Plus(Box::new(lhs), Box::new(rhs))
+            },
+            // 整数 + ポインタは入れ替える
+            (_, Some(_)) => {
+                let lhs = Self::scale(lhs, &rhs.ty);
+                NodeType::Plus(Box::new(rhs), Box::new(lhs))
+            },
+            _ => return self.new_arith(NodeType::Plus, lhs, rhs, span),
+        };
+        Ok(Node::new(kind, span))
+    }
+
+    fn new_sub(&self, lhs: Node, rhs: Node, span: Span) -> PResult<Node> {
+        match (lhs.ty.base(), rhs.ty.base()) {
+            // ポインタ同士の差は要素数にする
+            (Some(l), Some(r)) => {
+                if l != r {
+                    return Err(self.invalid_operands(&lhs, &rhs, span));
+                }
+                let size = Node::new(NodeType::Num(l.size() as i64, Type::Long), span);
+                let diff = Node::with_type(NodeType::Minus(Box::new(lhs), Box::new(rhs)), Type::Long, span);
+                Ok(Node::with_type(NodeType::Div(Box::new(diff), Box::new(size)), Type::Long, span))
+            },
+            (Some(_), _) => {
+                let rhs = Self::scale(rhs, &lhs.ty);
+                Ok(Node::new(NodeType::Minus(Box::new(lhs), Box::new(rhs)), span))
+            },
+            (_, Some(_)) => Err(self.invalid_operands(&lhs, &rhs, span)),
+            _ => self.new_arith(NodeType::Minus, lhs, rhs, span),
+        }
+    }
+
+    // 通常の算術変換: 両辺を共通の型に揃えて64ビットで計算し、結果を共通の型に戻す
+    fn new_arith(&self, kind: fn(Box<Node>, Box<Node>) -> NodeType, lhs: Node, rhs: Node, span: Span) -> PResult<Node> {
+        if !lhs.ty.is_integer() || !rhs.ty.is_integer() {
+            return Err(self.invalid_operands(&lhs, &rhs, span));
+        }
+        let ty = Type::arith(&lhs.ty, &rhs.ty);
+        let wide = widen(&ty);
+        let (lhs, rhs) = (new_cast(lhs, wide.clone()), new_cast(rhs, wide.clone()));
+        let node = Node::with_type(kind(Box::new(lhs), Box::new(rhs)), wide, span);
+        Ok(new_cast(node, ty))
+    }
+
+    // 整数同士なら共通の型に揃えて比べる
+    // ポインタ同士やポインタと整数はそのまま比べる
+    fn new_cmp(&self, kind: fn(Box<Node>, Box<Node>) -> NodeType, lhs: Node, rhs: Node, span: Span) -> PResult<Node> {
+        self.check_scalar(&lhs, &rhs, span)?;
+        let (lhs, rhs) = if lhs.ty.is_integer() && rhs.ty.is_integer() {
+            let wide = widen(&Type::arith(&lhs.ty, &rhs.ty));
+            (new_cast(lhs, wide.clone()), new_cast(rhs, wide))
+        } else {
+            (lhs, rhs)
+        };
+        Ok(Node::with_type(kind(Box::new(lhs), Box::new(rhs)), Type::Int, span))
     }
 
-    // mul = unary ("*" unary | "/" unary)*
-    fn mul(&mut self) -> NodeType {
-        let mut unary = self.unary();
+    // mul = cast ("*" cast | "/" cast | "%" cast)*
+    fn mul(&mut self) -> PResult<Node> {
+        let mut cast = self.cast()?;
         loop {
+            let span = self.peek().span;
             if self.consume(TokenType::Mul) {
-                let rhs = Box::new(self.unary());
-                unary = NodeType::Mul(Box::new(unary), rhs);
+                let rhs = self.cast()?;
+                cast = self.new_arith(NodeType::Mul, cast, rhs, span)?;
                 continue;
             } else if self.consume(TokenType::Div) {
-                let rhs = Box::new(self.unary());
-                unary = NodeType::Div(Box::new(unary), rhs);
+                let rhs = self.cast()?;
+                cast = self.new_arith(NodeType::Div, cast, rhs, span)?;
+                continue;
+            } else if self.consume(TokenType::Mod) {
+                let rhs = self.cast()?;
+                cast = self.new_arith(NodeType::Mod, cast, rhs, span)?;
                 continue;
             }
             break;
         }
-        unary
+        Ok(cast)
+    }
+
+    // cast = "(" type_name ")" cast | unary
+    fn cast(&mut self) -> PResult<Node> {
+        let next = &self.tokens[(self.pos + 1).min(self.tokens.len() - 1)];
+        if self.peek().kind != TokenType::LeftParen || !self.is_type_token(next) {
+            return self.unary();
+        }
+        let span = self.next().span;
+        let ty = self.type_name()?;
+        self.expect(TokenType::RightParen)?;
+        let node = self.cast()?;
+        // voidへの変換は値を捨てるだけ
+        if ty == Type::Void {
+            return Ok(Node::with_type(NodeType::Cast(Box::new(node)), ty, span));
+        }
+        let scalar = |ty: &Type| ty.is_integer() || matches!(ty, Type::Ptr(_));
+        if !scalar(&ty) {
+            let message = format!("used type '{}' where arithmetic or pointer type is required", ty);
+            return Err(self.error(span, message));
+        }
+        if !scalar(&node.ty) && !matches!(node.ty, Type::Array(..)) {
+            let message = format!("operand of type '{}' where arithmetic or pointer type is required", node.ty);
+            return Err(self.error(node.span, message));
+        }
+        Ok(new_cast(node, ty))
     }
 
-    // unary = ("+" | "-")? primary
-    fn unary(&mut self) -> NodeType {
+    // unary = ("+" | "-" | "&" | "*" | "!" | "~") cast
+    //       | ("++" | "--") unary
+    //       | "sizeof" unary
+    //       | ("sizeof" | "_Alignof") "(" type_name ")"
+    //       | postfix
+    fn unary(&mut self) -> PResult<Node> {
+        let span = self.peek().span;
         if self.consume(TokenType::Minus) {
-            let primary = Box::new(self.primary());
-            NodeType::Negative(primary)
+            let cast = self.cast()?;
+            if !cast.ty.is_integer() {
+                let message = format!("invalid argument type '{}' to unary expression", cast.ty);
+                return Err(self.error(span, message));
+            }
+            let ty = cast.ty.promote();
+            let wide = widen(&ty);
+            let node = Node::with_type(NodeType::Negative(Box::new(new_cast(cast, wide.clone()))), wide, span);
+            Ok(new_cast(node, ty))
+        } else if self.consume(TokenType::Inc) {
+            let unary = self.unary()?;
+            self.new_inc_dec(unary, true, false, span)
+        } else if self.consume(TokenType::Dec) {
+            let unary = self.unary()?;
+            self.new_inc_dec(unary, false, false, span)
+        } else if self.consume(TokenType::BitNot) {
+            let cast = self.cast()?;
+            if !cast.ty.is_integer() {
+                let message = format!("invalid argument type '{}' to unary expression", cast.ty);
+                return Err(self.error(span, message));
+            }
+            let ty = cast.ty.promote();
+            let wide = widen(&ty);
+            let node = Node::with_type(NodeType::BitNot(Box::new(new_cast(cast, wide.clone()))), wide, span);
+            Ok(new_cast(node, ty))
         } else if self.consume(TokenType::Plus) {
-            self.primary()
+            let cast = self.cast()?;
+            if !cast.ty.is_integer() {
+                let message = format!("invalid argument type '{}' to unary expression", cast.ty);
+                return Err(self.error(span, message));
+            }
+            let ty = cast.ty.promote();
+            Ok(new_cast(cast, ty))
+        } else if self.consume(TokenType::Not) {
+            let cast = self.cast()?;
+            if !is_scalar(&cast.ty) {
+                let message = format!("invalid argument type '{}' to unary expression", cast.ty);
+                return Err(self.error(span, message));
+            }
+            Ok(Node::new(NodeType::Not(Box::new(cast)), span))
+        } else if self.consume(TokenType::BitAnd) {
+            let cast = Box::new(self.cast()?);
+            Ok(Node::new(NodeType::Addr(cast), span))
+        } else if self.consume(TokenType::Mul) {
+            let cast = self.cast()?;
+            self.new_deref(cast, span)
+        } else if self.consume(TokenType::Sizeof) {
+            // 式は評価せず型の大きさだけを使う
+            let ty = self.type_operand()?;
+            if !ty.is_complete() {
+                let message = format!("invalid application of 'sizeof' to an incomplete type '{}'", ty);
+                return Err(self.error(span, message));
+            }
+            Ok(Node::new(NodeType::Num(ty.size() as i64, Type::ULong), span))
+        } else if self.consume(TokenType::Alignof) {
+            let ty = self.type_operand()?;
+            if !ty.is_complete() {
+                let message = format!("invalid application of '_Alignof' to an incomplete type '{}'", ty);
+                return Err(self.error(span, message));
+            }
+            Ok(Node::new(NodeType::Num(ty.align() as i64, Type::ULong), span))
         } else {
-            self.primary()
+            self.postfix()
         }
     }
 
-    // primary = num | ident ("(" ")")? | "(" expr ")"
-    fn primary(&mut self) -> NodeType {
-        let t = &self.tokens[self.pos];
-        self.pos += 1;
-        match t {
-            TokenType::Num(val) => NodeType::Num(*val),
+    // sizeof, _Alignof の対象は括弧で囲んだ型名か式
+    fn type_operand(&mut self) -> PResult<Type> {
+        let next = &self.tokens[(self.pos + 1).min(self.tokens.len() - 1)];
+        if self.peek().kind == TokenType::LeftParen && self.is_type_token(next) {
+            self.pos += 1;
+            let ty = self.type_name()?;
+            self.expect(TokenType::RightParen)?;
+            return Ok(ty);
+        }
+        Ok(self.unary()?.ty)
+    }
+
+    fn new_deref(&self, node: Node, span: Span) -> PResult<Node> {
+        match node.ty.base() {
+            Some(Type::Void) => Err(self.error(span, "dereferencing 'void *' pointer".into())),
+            Some(_) => Ok(Node::new(NodeType::Deref(Box::new(node)), span)),
+            None => {
+                let message = format!("indirection requires pointer operand ('{}' invalid)", node.ty);
+                Err(self.error(span, message))
+            },
+        }
+    }
+
+    // postfix = primary ("[" expr "]" | "." ident | "->" ident | "++" | "--")*
+    // a[i] は *(a + i)、p->x は (*p).x と同じ
+    fn postfix(&mut self) -> PResult<Node> {
+        let mut node = self.primary()?;
+        loop {
+            let span = self.peek().span;
+            if self.consume(TokenType::LeftBracket) {
+                let index = self.expr()?;
+                self.expect(TokenType::RightBracket)?;
+                let add = self.new_add(node, index, span)?;
+                node = self.new_deref(add, span)?;
+                continue;
+            } else if self.consume(TokenType::Dot) {
+                node = self.member(node)?;
+                continue;
+            } else if self.consume(TokenType::Inc) {
+                node = self.new_inc_dec(node, true, true, span)?;
+                continue;
+            } else if self.consume(TokenType::Dec) {
+                node = self.new_inc_dec(node, false, true, span)?;
+                continue;
+            } else if self.consume(TokenType::Arrow) {
+                if let Some(Type::Struct(_)) = node.ty.base() {
+                    node = Node::new(NodeType::Deref(Box::new(node)), span);
+                } else {
+                    let message = format!("member reference type '{}' is not a pointer to a struct", node.ty);
+                    return Err(self.error(span, message));
+                }
+                node = self.member(node)?;
+                continue;
+            }
+            break;
+        }
+        Ok(node)
+    }
+
+    fn member(&mut self, node: Node) -> PResult<Node> {
+        let t = self.next();
+        let name = match t.kind {
+            TokenType::Ident(ident) => ident,
+            _ => {
+                self.pos -= 1;
+                return Err(self.error(t.span, "expected member name".into()));
+            }
+        };
+        let record = match &node.ty {
+            Type::Struct(record) => record.clone(),
+            ty => {
+                let message = format!("member reference base type '{}' is not a struct or union", ty);
+                return Err(self.error(t.span, message));
+            }
+        };
+        match record.find_member(name) {
+            Some((offset, ty)) => Ok(Node::with_type(NodeType::Member(Box::new(node), offset), ty, t.span)),
+            None => {
+                let message = format!("no member named '{}' in '{}'", name, node.ty);
+                Err(self.error(t.span, message))
+            }
+        }
+    }
+
+    // primary = num | char | str+ | ident ("(" (assign ("," assign)*)? ")")? | "(" expr ")"
+    fn primary(&mut self) -> PResult<Node> {
+        let t = self.next();
+        match &t.kind {
+            TokenType::Num(val, ty) => Ok(Node::new(NodeType::Num(*val, ty.clone()), t.span)),
+            // 文字定数はint
+            TokenType::CharLit(val) => Ok(Node::new(NodeType::Num(*val as i64, Type::Int), t.span)),
+            TokenType::Str(s) => {
+                // 隣接する文字列リテラルは連結する
+                let mut buf = s.clone();
+                while let TokenType::Str(s) = &self.peek().kind {
+                    buf.extend_from_slice(s);
+                    self.pos += 1;
+                }
+                Ok(Node::new(NodeType::Str(buf), t.span))
+            },
             TokenType::Ident(ident) => {
                 // 括弧が続かない場合はローカル変数
                 if !self.consume(TokenType::LeftParen) {
-                    let offset;
-                    if let Some(lvar) = self.find_lvar(t.clone()) {
-                        offset = lvar.offset;
-                    } else {
-                        let tail = self.locals.last();
-                        offset = match tail {
-                            Some(var) => var.offset + 4,
-                            None => 4,
-                        };
-                    }
-                    let lvar = LVar {
-                        name: ident.into(),
-                        len: ident.len() as i32,
-                        offset
-                    };
-                    self.locals.push(lvar);
-                    return NodeType::LVar(offset);
+                    return Ok(self.lvar(t));
                 }
 
                 // 括弧が続くなら関数呼び出し
+                // 定義されていない関数はintを返すものとして扱う
+                let ty = match self.funcs.get(ident) {
+                    Some(Type::Func(ret)) => (**ret).clone(),
+                    _ => Type::Int,
+                };
                 let mut args = vec![];
-                if self.consume(TokenType::RightParen) {
-                    return NodeType::Call(ident.clone(), args);
+                if !self.consume(TokenType::RightParen) {
+                    args.push(self.assign()?);
+                    while self.consume(TokenType::Comma) {
+                        args.push(self.assign()?);
+                    }
+                    self.expect(TokenType::RightParen)?;
                 }
 
-                // TODO: 参考ソースではassignだが、一旦引数での直接代入はサポートしない
-                args.push(self.primary());
-                while self.consume(TokenType::Comma) {
-                    args.push(self.primary());
+                // 戻り値のraxは8バイトより小さい型だと上位が不定なので拡張し直す
+                if ty.is_integer() && ty.size() < 8 {
+                    let call = Node::with_type(NodeType::Call(*ident, args), widen(&ty), t.span);
+                    return Ok(new_cast(call, ty));
                 }
-                self.expect(TokenType::RightParen);
-                NodeType::Call(ident.clone(), args)
+                Ok(Node::with_type(NodeType::Call(*ident, args), ty, t.span))
             },
             TokenType::LeftParen => {
-                let nt = self.expr();
-                self.expect(TokenType::RightParen);
-                nt
+                let nt = self.expr()?;
+                self.expect(TokenType::RightParen)?;
+                Ok(nt)
             },
-            _ => panic!("failed primary")
+            _ => {
+                // 読み飛ばしの起点にするため消費しない
+                self.pos -= 1;
+                Err(self.error(t.span, "expected expression".into()))
+            }
         }
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,155 @@
+use crate::gen_ir::{Function, IROp, Program, IR};
+use crate::gen_x86::NUM_REGS;
+use crate::diagnostic::Diagnostic;
+use std::collections::{HashMap, HashSet};
+
+// 最後の2個はメモリに追い出した値を読み書きするために空けておく
+const NUM_ALLOC: usize = NUM_REGS - 2;
+const SCRATCH: [usize; 2] = [NUM_REGS - 2, NUM_REGS - 1];
+
+// 仮想レジスタを物理レジスタに割り当てる
+// Killされたレジスタは解放して次の値に使い回す
+struct Allocator {
+    live: HashMap<usize, usize>,
+    assigned: HashMap<usize, usize>,
+    used: [bool; NUM_ALLOC],
+}
+
+impl Allocator {
+    fn new() -> Self {
+        Allocator { live: HashMap::new(), assigned: HashMap::new(), used: [false; NUM_ALLOC] }
+    }
+
+    fn alloc(&mut self, r: usize) -> Option<usize> {
+        if let Some(&p) = self.live.get(&r) {
+            return Some(p);
+        }
+        let p = self.used.iter().position(|used| !used)?;
+        self.used[p] = true;
+        self.live.insert(r, p);
+        self.assigned.insert(r, p);
+        Some(p)
+    }
+
+    fn kill(&mut self, r: usize) {
+        if let Some(p) = self.live.remove(&r) {
+            self.used[p] = false;
+        }
+    }
+}
+
+// lhsの値を読む命令
+fn reads_lhs(op: &IROp) -> bool {
+    use self::IROp::*;
+    !matches!(op, Imm | LabelAddr(_) | GlobalAddr(_) | Bprel | Call(..) | Load(..) | Mov)
+}
+
+// lhsに結果を書き込む命令
+fn writes_lhs(op: &IROp) -> bool {
+    use self::IROp::*;
+    !matches!(op, Return | Unless | Push | Store(_) | Memcpy(_) | Kill)
+}
+
+fn vregs(ir: &IR) -> [Option<usize>; 2] {
+    let (lhs, rhs) = ir.regs();
+    [ir.lhs.filter(|_| lhs), ir.rhs.filter(|_| rhs)]
+}
+
+// 仮想レジスタが最後に使われる位置
+fn last_uses(code: &[IR]) -> HashMap<usize, usize> {
+    let mut last = HashMap::new();
+    for (i, ir) in code.iter().enumerate() {
+        for r in vregs(ir).iter().flatten() {
+            last.insert(*r, i);
+        }
+    }
+    last
+}
+
+// 足りなくなったら、その時点で生きている中で一番後まで使う仮想レジスタを返す
+fn try_alloc(code: &[IR], spilled: &HashSet<usize>, last: &HashMap<usize, usize>) -> Result<HashMap<usize, usize>, usize> {
+    let mut allocator = Allocator::new();
+    for ir in code.iter() {
+        for r in vregs(ir).iter().flatten() {
+            if spilled.contains(r) || allocator.alloc(*r).is_some() {
+                continue;
+            }
+            let victim = allocator.live.keys().chain(Some(r)).max_by_key(|v| (last[v], **v)).unwrap();
+            return Err(*victim);
+        }
+        if ir.op == IROp::Kill {
+            if let Some(r) = ir.lhs {
+                allocator.kill(r);
+            }
+        }
+    }
+    Ok(allocator.assigned)
+}
+
+// 追い出した仮想レジスタは使うたびにスタックとの間で読み書きする
+// 分岐をまたいでも場所が変わらないように、生きている間ずっとスタックに置く
+fn rewrite(f: &mut Function, map: &HashMap<usize, usize>, spilled: &HashSet<usize>) {
+    let mut slots = HashMap::new();
+    let mut spilled: Vec<usize> = spilled.iter().copied().collect();
+    spilled.sort_unstable();
+    for r in spilled {
+        f.stacksize = f.stacksize.div_ceil(8) * 8 + 8;
+        slots.insert(r, f.stacksize);
+    }
+
+    let mut code = vec![];
+    for mut ir in std::mem::take(&mut f.code) {
+        let [lhs, rhs] = vregs(&ir);
+        if ir.op == IROp::Kill && lhs.is_some_and(|r| slots.contains_key(&r)) {
+            continue;
+        }
+        let mut spill = None;
+        if let Some(r) = lhs {
+            match slots.get(&r) {
+                Some(&off) => {
+                    if reads_lhs(&ir.op) {
+                        code.push(IR { op: IROp::Reload, lhs: Some(SCRATCH[0]), rhs: Some(off) });
+                    }
+                    if writes_lhs(&ir.op) {
+                        spill = Some(off);
+                    }
+                    ir.lhs = Some(SCRATCH[0]);
+                },
+                None => ir.lhs = Some(map[&r]),
+            }
+        }
+        if let Some(r) = rhs {
+            match slots.get(&r) {
+                Some(&off) => {
+                    code.push(IR { op: IROp::Reload, lhs: Some(SCRATCH[1]), rhs: Some(off) });
+                    ir.rhs = Some(SCRATCH[1]);
+                },
+                None => ir.rhs = Some(map[&r]),
+            }
+        }
+        code.push(ir);
+        if let Some(off) = spill {
+            code.push(IR { op: IROp::Spill, lhs: Some(SCRATCH[0]), rhs: Some(off) });
+        }
+    }
+    f.code = code;
+}
+
+fn alloc_function(f: &mut Function) {
+    let last = last_uses(&f.code);
+    let mut spilled = HashSet::new();
+    let map = loop {
+        match try_alloc(&f.code, &spilled, &last) {
+            Ok(map) => break map,
+            Err(victim) => spilled.insert(victim),
+        };
+    };
+    rewrite(f, &map, &spilled);
+}
+
+pub fn alloc_regs(mut program: Program) -> Result<Program, Vec<Diagnostic>> {
+    for f in program.funcs.iter_mut() {
+        alloc_function(f);
+    }
+    Ok(program)
+}
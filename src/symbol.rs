@@ -0,0 +1,129 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::fmt;
+use std::hash::{BuildHasherDefault, Hasher};
+
+// インターンした識別子
+// 比較やハッシュは番号で行い、文字列が必要な時だけ引く
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Symbol(u32);
+
+// 予約語は先頭から固定の番号で登録しておく
+const KEYWORDS: [&str; 19] = [
+    "if", "else", "while", "for", "return",
+    "void", "char", "short", "int", "long",
+    "sizeof", "static", "struct", "union", "enum",
+    "typedef", "_Alignof", "signed", "unsigned",
+];
+
+pub mod kw {
+    use super::Symbol;
+
+    pub const IF: Symbol = Symbol(0);
+    pub const ELSE: Symbol = Symbol(1);
+    pub const WHILE: Symbol = Symbol(2);
+    pub const FOR: Symbol = Symbol(3);
+    pub const RETURN: Symbol = Symbol(4);
+    pub const VOID: Symbol = Symbol(5);
+    pub const CHAR: Symbol = Symbol(6);
+    pub const SHORT: Symbol = Symbol(7);
+    pub const INT: Symbol = Symbol(8);
+    pub const LONG: Symbol = Symbol(9);
+    pub const SIZEOF: Symbol = Symbol(10);
+    pub const STATIC: Symbol = Symbol(11);
+    pub const STRUCT: Symbol = Symbol(12);
+    pub const UNION: Symbol = Symbol(13);
+    pub const ENUM: Symbol = Symbol(14);
+    pub const TYPEDEF: Symbol = Symbol(15);
+    pub const ALIGNOF: Symbol = Symbol(16);
+    pub const SIGNED: Symbol = Symbol(17);
+    pub const UNSIGNED: Symbol = Symbol(18);
+}
+
+// 識別子は短いので、SipHashではなく8バイトずつ掛けて混ぜるだけの軽いハッシュを使う
+#[derive(Default)]
+struct FxHasher {
+    hash: u64,
+}
+
+const SEED: u64 = 0x51_7c_c1_b7_27_22_0a_95;
+
+impl FxHasher {
+    fn add(&mut self, word: u64) {
+        self.hash = (self.hash.rotate_left(5) ^ word).wrapping_mul(SEED);
+    }
+}
+
+impl Hasher for FxHasher {
+    fn write(&mut self, bytes: &[u8]) {
+        let mut chunks = bytes.chunks_exact(8);
+        for chunk in chunks.by_ref() {
+            self.add(u64::from_le_bytes(chunk.try_into().unwrap()));
+        }
+        for &b in chunks.remainder() {
+            self.add(b as u64);
+        }
+    }
+
+    fn write_u8(&mut self, i: u8) {
+        self.add(i as u64);
+    }
+
+    fn finish(&self) -> u64 {
+        self.hash
+    }
+}
+
+struct Interner {
+    map: HashMap<&'static str, Symbol, BuildHasherDefault<FxHasher>>,
+    names: Vec<&'static str>,
+}
+
+impl Interner {
+    fn new() -> Self {
+        let mut interner = Interner { map: HashMap::default(), names: vec![] };
+        for name in KEYWORDS.iter() {
+            interner.intern(name);
+        }
+        interner
+    }
+
+    fn intern(&mut self, name: &str) -> Symbol {
+        if let Some(sym) = self.map.get(name) {
+            return *sym;
+        }
+        // 名前はコンパイルが終わるまで使うので解放しない
+        let name: &'static str = Box::leak(name.to_string().into_boxed_str());
+        let sym = Symbol(self.names.len() as u32);
+        self.map.insert(name, sym);
+        self.names.push(name);
+        sym
+    }
+}
+
+// コンパイルは1スレッドで行うので、ロックを取らずにスレッドごとの表を使う
+// 別のスレッドで作ったSymbolは使えない
+thread_local! {
+    static INTERNER: RefCell<Interner> = RefCell::new(Interner::new());
+}
+
+impl Symbol {
+    pub fn intern(name: &str) -> Self {
+        INTERNER.with(|interner| interner.borrow_mut().intern(name))
+    }
+
+    pub fn as_str(self) -> &'static str {
+        INTERNER.with(|interner| interner.borrow().names[self.0 as usize])
+    }
+
+    pub fn is_keyword(self) -> bool {
+        (self.0 as usize) < KEYWORDS.len()
+    }
+}
+
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
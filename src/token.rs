@@ -1,151 +1,512 @@
-use std::collections::HashMap;
-use once_cell::sync::Lazy;
+use crate::{Span, Token, TokenType};
+use crate::symbol::{kw, Symbol};
+use crate::types::Type;
+use crate::diagnostic::{Diagnostic, SourceFile, E_BAD_ESCAPE, E_BAD_NUMBER, E_UNKNOWN_CHAR, E_UNTERMINATED};
 
-use crate::TokenType;
-use crate::CharacterType;
+pub fn tokenize(file: &SourceFile) -> Result<Vec<Token>, Vec<Diagnostic>> {
+    let mut lexer = Lexer::new(file);
+    let tokens: Vec<Token> = lexer.by_ref().collect();
+    if !lexer.errors.is_empty() {
+        return Err(lexer.errors);
+    }
+    Ok(tokens)
+}
 
-pub fn tokenize(src: &str) -> Vec<TokenType> {
-    let mut tokenizer = Tokenizer::new(src.chars().collect());
-    tokenizer.scan(&keyword_map());
-    tokenizer.tokens
+// 予約語のシンボルに対応するトークン
+fn keyword(sym: Symbol) -> Option<TokenType> {
+    let t = match sym {
+        kw::IF => TokenType::If,
+        kw::ELSE => TokenType::Else,
+        kw::WHILE => TokenType::While,
+        kw::FOR => TokenType::For,
+        kw::RETURN => TokenType::Return,
+        kw::VOID => TokenType::Void,
+        kw::CHAR => TokenType::Char,
+        kw::SHORT => TokenType::Short,
+        kw::INT => TokenType::Int,
+        kw::LONG => TokenType::Long,
+        kw::SIZEOF => TokenType::Sizeof,
+        kw::STATIC => TokenType::Static,
+        kw::STRUCT => TokenType::Struct,
+        kw::UNION => TokenType::Union,
+        kw::ENUM => TokenType::Enum,
+        kw::TYPEDEF => TokenType::Typedef,
+        kw::ALIGNOF => TokenType::Alignof,
+        kw::SIGNED => TokenType::Signed,
+        kw::UNSIGNED => TokenType::Unsigned,
+        _ => return None,
+    };
+    Some(t)
 }
 
-fn keyword_map() -> HashMap<String, TokenType> {
-    let mut map = HashMap::new();
-    map.insert("if".into(), TokenType::If);
-    map.insert("else".into(), TokenType::Else);
-    map.insert("while".into(), TokenType::While);
-    map.insert("for".into(), TokenType::For);
-    map.insert("return".into(), TokenType::Return);
-    map
+fn is_ident_head(b: u8) -> bool {
+    b.is_ascii_alphabetic() || b == b'_'
 }
 
-#[derive(Debug, Clone)]
-struct Symbol {
-    name: &'static str,
-    ty: TokenType,
+fn is_ident_tail(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b == b'_'
 }
 
-impl Symbol {
-    fn new(name: &'static str, ty: TokenType) -> Self {
-        Symbol { name, ty }
-    }
+// ソースをバイト列のまま走査し、トークンを1つずつ返す
+// 最後にEofトークンを1度だけ返して終わる
+pub struct Lexer<'a> {
+    file: usize,
+    text: &'a str,
+    src: &'a [u8],
+    pos: usize,
+    line: usize,
+    col: usize,
+    errors: Vec<Diagnostic>,
+    done: bool,
 }
 
-static SYMBOLS: Lazy<Vec<Symbol>> = Lazy::new(|| [
-    Symbol::new("==", TokenType::Eq),
-    Symbol::new("!=", TokenType::Ne),
-    Symbol::new("<=", TokenType::Le),
-    Symbol::new(">=", TokenType::Ge),
-].to_vec());
+impl<'a> Iterator for Lexer<'a> {
+    type Item = Token;
 
-struct Tokenizer {
-    src: Vec<char>,
-    pos: usize,
-    tokens: Vec<TokenType>
+    fn next(&mut self) -> Option<Token> {
+        if self.done {
+            return None;
+        }
+        let token = self.scan();
+        if token.kind == TokenType::Eof {
+            self.done = true;
+        }
+        Some(token)
+    }
 }
 
-impl Tokenizer {
-    fn new(src: Vec<char>) -> Self {
-        Tokenizer {
-            src,
+impl<'a> Lexer<'a> {
+    pub fn new(file: &'a SourceFile) -> Self {
+        Lexer {
+            file: file.id,
+            text: &file.src,
+            src: file.src.as_bytes(),
             pos: 0,
-            tokens: Vec::from(vec![])
+            line: 1,
+            col: 1,
+            errors: vec![],
+            done: false,
         }
     }
 
-    fn get_character(&self, advance_from_pos: usize) -> Option<CharacterType> {
-        self.src.get(self.pos + advance_from_pos).map(|ch| {
-            if ch == &'\n' {
-                CharacterType::NewLine
-            } else if ch == &'\r' {
-                CharacterType::CarriageReturn
-            } else if ch == &' ' || ch == &'\t' {
-                CharacterType::Whitespace
-            } else if ch.is_alphabetic() || ch == &'_' {
-                CharacterType::Alphabetic
-            } else if ch.is_ascii_digit() {
-                CharacterType::Digit
-            } else {
-                CharacterType::NonAlphabetic(*ch)
+    // ここまでに見つかったエラー
+    pub fn errors(&self) -> &[Diagnostic] {
+        &self.errors
+    }
+
+    fn peek(&self, n: usize) -> u8 {
+        self.src.get(self.pos + n).copied().unwrap_or(0)
+    }
+
+    // 現在位置から始まるトークンのSpan(長さは後で決める)
+    fn span(&self) -> Span {
+        Span {
+            file: self.file,
+            offset: self.pos,
+            len: 0,
+            line: self.line,
+            col: self.col,
+        }
+    }
+
+    // 行・列を数えながらnバイト進める
+    // 列はUTF-8の先頭バイトだけ数える
+    fn advance(&mut self, n: usize) {
+        for &b in &self.src[self.pos..self.pos + n] {
+            if b == b'\n' {
+                self.line += 1;
+                self.col = 1;
+            } else if b & 0xc0 != 0x80 {
+                self.col += 1;
             }
-        })
-    }
-
-    fn scan(&mut self, keywords: &HashMap<String, TokenType>) -> Vec<TokenType> {
-        'outer: while let Some(head_char) = self.get_character(0) {
-            match head_char {
-                CharacterType::NewLine | CharacterType::CarriageReturn |
-                CharacterType::Whitespace => self.pos += 1,
-                CharacterType::Alphabetic => self.ident(&keywords),
-                CharacterType::Digit => self.number(),
-                CharacterType::NonAlphabetic(c) => {
-                    // Multi-letter symbol
-                    for symbol in SYMBOLS.iter() {
-                        let name = symbol.name;
-                        let len = name.len();
-                        if self.pos + len > self.src.len() {
-                            continue;
-                        }
-
-                        let first = &self.src[self.pos..(self.pos + len)];
-                        if name != first.iter().collect::<String>() {
-                            continue;
-                        }
-
-                        self.pos += len;
-                        self.tokens.push(symbol.ty.clone());
-                        continue 'outer;
-                    }
+        }
+        self.pos += n;
+    }
 
-                    // Single-letter symbol
-                    if let Some(ty) = TokenType::new_single_letter(c) {
-                        self.pos += 1;
-                        self.tokens.push(ty);
-                        continue 'outer;
+    // startからnバイト進めてトークンにする
+    fn token(&mut self, kind: TokenType, start: Span, n: usize) -> Token {
+        self.advance(n);
+        Token { kind, span: Span { len: self.pos - start.offset, ..start } }
+    }
+
+    fn error(&mut self, code: &'static str, start: Span, message: String) {
+        let span = Span { len: self.pos - start.offset, ..start };
+        self.errors.push(Diagnostic::error(code, span, message));
+    }
+
+    fn scan(&mut self) -> Token {
+        loop {
+            self.skip_whitespace();
+            let start = self.span();
+            let c = match self.src.get(self.pos) {
+                Some(c) => *c,
+                None => return self.token(TokenType::Eof, start, 0),
+            };
+
+            let token = match c {
+                b'/' if self.peek(1) == b'/' => {
+                    self.line_comment();
+                    continue;
+                },
+                b'/' if self.peek(1) == b'*' => {
+                    self.block_comment();
+                    continue;
+                },
+                b'"' => self.string(),
+                b'\'' => self.char_literal(),
+                b'0'..=b'9' => self.number(),
+                c if is_ident_head(c) => self.ident(),
+                _ => {
+                    if let Some((kind, len)) = self.punct() {
+                        return self.token(kind, start, len);
+                    }
+                    // ASCII以外の文字も識別子として読む
+                    let ch = self.text[self.pos..].chars().next().unwrap();
+                    if ch.is_alphabetic() {
+                        self.ident()
+                    } else {
+                        self.unknown(ch);
+                        None
                     }
-                    panic!("not support symbol");
+                }
+            };
+            if let Some(token) = token {
+                return token;
+            }
+        }
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.pos < self.src.len() {
+            match self.src[self.pos] {
+                b'\n' => {
+                    self.pos += 1;
+                    self.line += 1;
+                    self.col = 1;
+                },
+                b' ' | b'\t' | b'\r' => {
+                    self.pos += 1;
+                    self.col += 1;
+                },
+                _ => return,
+            }
+        }
+    }
+
+    // 記号とそのバイト長(最長一致)
+    fn punct(&self) -> Option<(TokenType, usize)> {
+        use crate::TokenType::*;
+        let c1 = self.peek(1);
+        let c2 = self.peek(2);
+        let t = match self.peek(0) {
+            b'+' => match c1 {
+                b'+' => (Inc, 2),
+                b'=' => (AddAssign, 2),
+                _ => (Plus, 1),
+            },
+            b'-' => match c1 {
+                b'-' => (Dec, 2),
+                b'=' => (SubAssign, 2),
+                b'>' => (Arrow, 2),
+                _ => (Minus, 1),
+            },
+            b'*' if c1 == b'=' => (MulAssign, 2),
+            b'*' => (Mul, 1),
+            b'/' if c1 == b'=' => (DivAssign, 2),
+            b'/' => (Div, 1),
+            b'%' if c1 == b'=' => (ModAssign, 2),
+            b'%' => (Mod, 1),
+            b'=' if c1 == b'=' => (Eq, 2),
+            b'=' => (Assign, 1),
+            b'!' if c1 == b'=' => (Ne, 2),
+            b'!' => (Not, 1),
+            b'<' => match (c1, c2) {
+                (b'<', b'=') => (ShlAssign, 3),
+                (b'<', _) => (Shl, 2),
+                (b'=', _) => (Le, 2),
+                _ => (Lt, 1),
+            },
+            b'>' => match (c1, c2) {
+                (b'>', b'=') => (ShrAssign, 3),
+                (b'>', _) => (Shr, 2),
+                (b'=', _) => (Ge, 2),
+                _ => (Gt, 1),
+            },
+            b'&' => match c1 {
+                b'&' => (LogAnd, 2),
+                b'=' => (AndAssign, 2),
+                _ => (BitAnd, 1),
+            },
+            b'|' => match c1 {
+                b'|' => (LogOr, 2),
+                b'=' => (OrAssign, 2),
+                _ => (BitOr, 1),
+            },
+            b'^' if c1 == b'=' => (XorAssign, 2),
+            b'^' => (BitXor, 1),
+            b'.' if c1 == b'.' && c2 == b'.' => (Ellipsis, 3),
+            b'.' => (Dot, 1),
+            b'#' if c1 == b'#' => (HashHash, 2),
+            b'#' => (Hash, 1),
+            b'~' => (BitNot, 1),
+            b'?' => (Question, 1),
+            b':' => (Colon, 1),
+            b';' => (Semicolon, 1),
+            b',' => (Comma, 1),
+            b'(' => (LeftParen, 1),
+            b')' => (RightParen, 1),
+            b'{' => (LeftBrace, 1),
+            b'}' => (RightBrace, 1),
+            b'[' => (LeftBracket, 1),
+            b']' => (RightBracket, 1),
+            _ => return None,
+        };
+        Some(t)
+    }
+
+    // "//" から行末までを読み飛ばす
+    fn line_comment(&mut self) {
+        let len = self.src[self.pos..]
+            .iter()
+            .position(|b| *b == b'\n')
+            .unwrap_or(self.src.len() - self.pos);
+        self.advance(len);
+    }
+
+    // "/*" から "*/" までを読み飛ばす
+    fn block_comment(&mut self) {
+        let start = Span { len: 2, ..self.span() };
+        let body = &self.src[self.pos + 2..];
+        match body.windows(2).position(|w| w == b"*/") {
+            Some(i) => self.advance(i + 4),
+            None => {
+                self.advance(self.src.len() - self.pos);
+                let message = "unterminated comment".to_string();
+                self.errors.push(Diagnostic::error(E_UNTERMINATED, start, message));
+            }
+        }
+    }
+
+    // 引用符で囲まれた文字列を読み、エスケープを解決したバイト列を返す
+    // 閉じられていなければNone
+    fn quoted(&mut self, quote: u8, start: Span) -> Option<Vec<u8>> {
+        self.advance(1);
+        let mut buf = vec![];
+        loop {
+            match self.src.get(self.pos) {
+                None | Some(b'\n') => {
+                    let message = format!("missing terminating {} character", quote as char);
+                    let span = Span { len: 1, ..start };
+                    self.errors.push(Diagnostic::error(E_UNTERMINATED, span, message));
+                    return None;
+                },
+                Some(c) if *c == quote => {
+                    self.advance(1);
+                    return Some(buf);
+                },
+                Some(b'\\') => {
+                    self.advance(1);
+                    let c = self.escape();
+                    buf.push(c);
                 },
-                CharacterType::Unknown(_) => panic!("not support character")
+                Some(c) => {
+                    buf.push(*c);
+                    self.advance(1);
+                }
             }
         }
-        self.tokens.clone()
     }
 
-    fn ident(&mut self, keywords: &HashMap<String, TokenType>) {
-        let mut len = 1;
-        while let Some(c2) = self.src.get(self.pos + len) {
-            if c2.is_alphabetic() || c2.is_ascii_digit() || c2 == &'_' {
+    // "\" の直後からエスケープシーケンスを1つ読む
+    fn escape(&mut self) -> u8 {
+        let start = Span { offset: self.pos - 1, col: self.col - 1, ..self.span() };
+        let c = match self.src.get(self.pos) {
+            Some(c) => *c,
+            None => return b'\\',
+        };
+
+        // \ooo
+        if (b'0'..=b'7').contains(&c) {
+            let mut val: u32 = 0;
+            let mut len = 0;
+            while len < 3 && (b'0'..=b'7').contains(&self.peek(0)) {
+                val = val * 8 + (self.peek(0) - b'0') as u32;
+                len += 1;
+                self.advance(1);
+            }
+            if val > 0xff {
+                self.error(E_BAD_ESCAPE, start, "octal escape sequence out of range".into());
+            }
+            return val as u8;
+        }
+
+        // \xHH
+        if c == b'x' {
+            self.advance(1);
+            let mut val: u32 = 0;
+            let mut len = 0;
+            while let Some(d) = (self.peek(0) as char).to_digit(16) {
+                val = val.saturating_mul(16).saturating_add(d);
                 len += 1;
-                continue;
+                self.advance(1);
+            }
+            if len == 0 {
+                self.error(E_BAD_ESCAPE, start, "\\x used with no following hex digits".into());
+            } else if val > 0xff {
+                self.error(E_BAD_ESCAPE, start, "hex escape sequence out of range".into());
             }
-            break;
+            return val as u8;
         }
 
-        let name: String = self.src[self.pos..(self.pos + len)].iter().collect();
-        let t;
-        if let Some(keyword) = keywords.get(&name) {
-            t = keyword.clone();
-        } else {
-            t = TokenType::Ident(name.clone());
+        self.advance(1);
+        match c {
+            b'a' => 0x07,
+            b'b' => 0x08,
+            b't' => b'\t',
+            b'n' => b'\n',
+            b'v' => 0x0b,
+            b'f' => 0x0c,
+            b'r' => b'\r',
+            b'e' => 0x1b,
+            // \\ \' \" \? と未知のエスケープはその文字自身
+            _ => c,
         }
+    }
 
-        self.pos += len;
-        self.tokens.push(t);
+    fn string(&mut self) -> Option<Token> {
+        let start = self.span();
+        let buf = self.quoted(b'"', start)?;
+        Some(self.token(TokenType::Str(buf), start, 0))
     }
 
-    fn number(&mut self) {
-        let mut sum: i32 = 0;
-        let mut len = 0;
-        for c in self.src[self.pos..].iter() {
-            if let Some(val) = c.to_digit(10) {
-                sum = sum * 10 as i32 + val as i32;
-                len += 1;
+    fn char_literal(&mut self) -> Option<Token> {
+        let start = self.span();
+        let buf = self.quoted(b'\'', start)?;
+        let val = match buf.len() {
+            0 => {
+                self.error(E_BAD_ESCAPE, start, "empty character constant".into());
+                0
+            },
+            // charは符号付き
+            1 => buf[0] as i8 as i32,
+            // 複数文字の場合はgccと同じく上位から詰める
+            _ => buf.iter().fold(0, |acc, b| (acc << 8) | *b as i32),
+        };
+        Some(self.token(TokenType::CharLit(val), start, 0))
+    }
+
+    // エラーを記録して読み飛ばす
+    fn unknown(&mut self, c: char) {
+        let start = self.span();
+        self.advance(c.len_utf8());
+        self.error(E_UNKNOWN_CHAR, start, format!("not support character '{}'", c));
+    }
+
+    fn ident(&mut self) -> Option<Token> {
+        let start = self.span();
+        let mut end = self.pos;
+        while end < self.src.len() {
+            let b = self.src[end];
+            if is_ident_tail(b) {
+                end += 1;
+            } else if b >= 0x80 {
+                let ch = self.text[end..].chars().next().unwrap();
+                if !ch.is_alphabetic() {
+                    break;
+                }
+                end += ch.len_utf8();
             } else {
                 break;
             }
         }
-        self.pos += len;
-        self.tokens.push(TokenType::Num(sum as i32));
+
+        let sym = Symbol::intern(&self.text[self.pos..end]);
+        let t = match keyword(sym) {
+            Some(keyword) => keyword,
+            None => TokenType::Ident(sym),
+        };
+        Some(self.token(t, start, end - self.pos))
+    }
+
+    fn number(&mut self) -> Option<Token> {
+        let start = self.span();
+        // 英数字と"."が続く間を1つの数値として読む
+        let len = self.src[self.pos..]
+            .iter()
+            .position(|b| !(is_ident_tail(*b) || *b == b'.'))
+            .unwrap_or(self.src.len() - self.pos);
+
+        match parse_int(&self.text[self.pos..self.pos + len]) {
+            Ok((val, ty)) => Some(self.token(TokenType::Num(val as i64, ty), start, len)),
+            Err(message) => {
+                self.advance(len);
+                self.error(E_BAD_NUMBER, start, message);
+                None
+            }
+        }
     }
-}
\ No newline at end of file
+}
+
+// 整数リテラルの値と型を決める
+fn parse_int(text: &str) -> Result<(u64, Type), String> {
+    // 接頭辞はバイトのまま大文字小文字を区別せずに見る
+    let bytes = text.as_bytes();
+    let prefix = |c: u8| bytes.len() >= 2 && bytes[0] == b'0' && bytes[1].to_ascii_lowercase() == c;
+    let (radix, body) = if prefix(b'x') {
+        (16, &text[2..])
+    } else if prefix(b'b') {
+        (2, &text[2..])
+    } else if text.len() > 1 && text.starts_with('0') {
+        (8, &text[1..])
+    } else {
+        (10, text)
+    };
+
+    let len = body.find(|c: char| !c.is_digit(radix)).unwrap_or(body.len());
+    let (digits, suffix) = body.split_at(len);
+    if suffix.contains('.') {
+        return Err("floating constants are not supported".into());
+    }
+    if radix == 8 {
+        if let Some(c) = suffix.chars().next().filter(|c| c.is_ascii_digit()) {
+            return Err(format!("invalid digit '{}' in octal constant", c));
+        }
+    }
+    if digits.is_empty() && radix != 8 {
+        return Err(format!("invalid suffix '{}' on integer constant", &text[1..]));
+    }
+
+    // "lL"のような大文字小文字の混在は認めない
+    let (unsigned, long) = match suffix {
+        "" => (false, false),
+        "u" | "U" => (true, false),
+        "l" | "L" | "ll" | "LL" => (false, true),
+        "ul" | "uL" | "Ul" | "UL" | "lu" | "lU" | "Lu" | "LU" |
+        "ull" | "uLL" | "Ull" | "ULL" | "llu" | "llU" | "LLu" | "LLU" => (true, true),
+        _ => return Err(format!("invalid suffix '{}' on integer constant", suffix)),
+    };
+
+    let too_large = || "integer literal is too large to be represented in any integer type".to_string();
+    let mut val: u64 = 0;
+    for c in digits.chars() {
+        let d = c.to_digit(radix).unwrap() as u64;
+        val = val.checked_mul(radix as u64)
+            .and_then(|v| v.checked_add(d))
+            .ok_or_else(too_large)?;
+    }
+
+    // C11 6.4.4.1: 先頭から順に値が収まる型を選ぶ
+    let decimal = radix == 10;
+    let candidates: &[Type] = match (unsigned, long) {
+        (false, false) if decimal => &[Type::Int, Type::Long],
+        (false, false) => &[Type::Int, Type::UInt, Type::Long, Type::ULong],
+        (true, false) => &[Type::UInt, Type::ULong],
+        (false, true) if decimal => &[Type::Long],
+        (false, true) => &[Type::Long, Type::ULong],
+        (true, true) => &[Type::ULong],
+    };
+    candidates.iter()
+        .find(|ty| ty.can_represent(val))
+        .map(|ty| (val, ty.clone()))
+        .ok_or_else(too_large)
+}
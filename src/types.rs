@@ -0,0 +1,246 @@
+use std::cell::RefCell;
+use std::fmt;
+use std::rc::Rc;
+use crate::symbol::Symbol;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Type {
+    Void,   // void
+    Char,   // char, signed char
+    Short,  // short
+    Int,    // int
+    Long,   // long, long long
+    UChar,  // unsigned char
+    UShort, // unsigned short
+    UInt,   // unsigned int
+    ULong,  // unsigned long, unsigned long long
+    Ptr(Box<Type>),             // 指す先の型
+    Array(Box<Type>, usize),    // 要素の型, 要素数
+    Func(Box<Type>),            // 戻り値の型
+    Struct(Record),             // struct, union
+}
+
+// 構造体と共用体のメンバー
+#[derive(Debug, Clone)]
+pub struct Member {
+    pub name: Option<Symbol>,   // 無名の構造体・共用体のメンバーはNone
+    pub ty: Type,
+    pub offset: usize,
+}
+
+#[derive(Debug)]
+pub struct RecordData {
+    pub tag: Option<Symbol>,
+    pub is_union: bool,
+    pub members: Vec<Member>,
+    pub size: usize,
+    pub align: usize,
+    pub complete: bool, // 前方宣言だけならfalse
+}
+
+// 自己参照する構造体があるので、型は共有して後からメンバーを埋める
+// 同じ型かどうかは中身ではなく実体で比べる
+#[derive(Clone)]
+pub struct Record(pub Rc<RefCell<RecordData>>);
+
+impl Record {
+    pub fn new(tag: Option<Symbol>, is_union: bool) -> Self {
+        let data = RecordData { tag, is_union, members: vec![], size: 0, align: 1, complete: false };
+        Record(Rc::new(RefCell::new(data)))
+    }
+
+    // SysV x86-64 ABIに従ってメンバーを配置する
+    pub fn set_members(&self, mut members: Vec<Member>) {
+        let mut data = self.0.borrow_mut();
+        let mut offset = 0;
+        let mut align = 1;
+        for m in members.iter_mut() {
+            align = align.max(m.ty.align());
+            if data.is_union {
+                m.offset = 0;
+                offset = offset.max(m.ty.size());
+            } else {
+                offset = align_to(offset, m.ty.align());
+                m.offset = offset;
+                offset += m.ty.size();
+            }
+        }
+        data.members = members;
+        data.size = align_to(offset, align);
+        data.align = align;
+        data.complete = true;
+    }
+
+    // 無名のメンバーの中も探す
+    pub fn find_member(&self, name: Symbol) -> Option<(usize, Type)> {
+        for m in self.0.borrow().members.iter() {
+            if m.name == Some(name) {
+                return Some((m.offset, m.ty.clone()));
+            }
+            if let (None, Type::Struct(inner)) = (m.name, &m.ty) {
+                if let Some((offset, ty)) = inner.find_member(name) {
+                    return Some((m.offset + offset, ty));
+                }
+            }
+        }
+        None
+    }
+
+    // メンバーの配置を表示する
+    pub fn layout(&self) -> String {
+        let mut out = String::new();
+        self.dump(&mut out, 0, 0, &Type::Struct(self.clone()).to_string());
+        let data = self.0.borrow();
+        out.push_str(&format!("       | [sizeof={}, align={}]\n", data.size, data.align));
+        out
+    }
+
+    fn dump(&self, out: &mut String, base: usize, depth: usize, head: &str) {
+        out.push_str(&format!("{:>6} | {}{}\n", base, "  ".repeat(depth), head));
+        for m in self.0.borrow().members.iter() {
+            let head = match m.name {
+                Some(name) => format!("{} {}", m.ty, name),
+                None => m.ty.to_string(),
+            };
+            match &m.ty {
+                Type::Struct(inner) => inner.dump(out, base + m.offset, depth + 1, &head),
+                _ => out.push_str(&format!("{:>6} | {}{}\n", base + m.offset, "  ".repeat(depth + 1), head)),
+            }
+        }
+    }
+}
+
+impl PartialEq for Record {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+// メンバーに自分へのポインタがあると無限に辿るので名前だけ出す
+impl fmt::Debug for Record {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", Type::Struct(self.clone()))
+    }
+}
+
+pub fn align_to(n: usize, align: usize) -> usize {
+    n.div_ceil(align) * align
+}
+
+impl Type {
+    pub fn ptr_to(self) -> Self {
+        Type::Ptr(Box::new(self))
+    }
+
+    // ポインタと配列の要素の型
+    pub fn base(&self) -> Option<&Type> {
+        match self {
+            Type::Ptr(base) | Type::Array(base, _) => Some(base),
+            _ => None,
+        }
+    }
+
+    pub fn size(&self) -> usize {
+        match self {
+            Type::Void | Type::Func(_) => 1,
+            Type::Char | Type::UChar => 1,
+            Type::Short | Type::UShort => 2,
+            Type::Int | Type::UInt => 4,
+            Type::Long | Type::ULong | Type::Ptr(_) => 8,
+            Type::Array(base, len) => base.size() * len,
+            Type::Struct(record) => record.0.borrow().size,
+        }
+    }
+
+    pub fn align(&self) -> usize {
+        match self {
+            Type::Array(base, _) => base.align(),
+            Type::Struct(record) => record.0.borrow().align,
+            _ => self.size(),
+        }
+    }
+
+    // 前方宣言しかない構造体は大きさが分からない
+    pub fn is_complete(&self) -> bool {
+        match self {
+            Type::Void => false,
+            Type::Array(base, _) => base.is_complete(),
+            Type::Struct(record) => record.0.borrow().complete,
+            _ => true,
+        }
+    }
+
+    pub fn is_integer(&self) -> bool {
+        matches!(self, Type::Char | Type::Short | Type::Int | Type::Long
+            | Type::UChar | Type::UShort | Type::UInt | Type::ULong)
+    }
+
+    pub fn is_unsigned(&self) -> bool {
+        matches!(self, Type::UChar | Type::UShort | Type::UInt | Type::ULong)
+    }
+
+    // 値がこの型で表現できるか
+    pub fn can_represent(&self, val: u64) -> bool {
+        match self {
+            Type::Char => val <= i8::MAX as u64,
+            Type::Short => val <= i16::MAX as u64,
+            Type::UChar => val <= u8::MAX as u64,
+            Type::UShort => val <= u16::MAX as u64,
+            Type::Int => val <= i32::MAX as u64,
+            Type::UInt => val <= u32::MAX as u64,
+            Type::Long => val <= i64::MAX as u64,
+            Type::ULong => true,
+            _ => false,
+        }
+    }
+
+    // 整数拡張: intより小さい型はintとして計算する
+    pub fn promote(&self) -> Type {
+        match self {
+            Type::Char | Type::Short | Type::UChar | Type::UShort => Type::Int,
+            _ => self.clone(),
+        }
+    }
+
+    // 算術演算の結果の型
+    // 拡張した後、大きい方の型に揃える。同じ大きさなら符号無しを優先する
+    pub fn arith(lhs: &Type, rhs: &Type) -> Type {
+        let (lhs, rhs) = (lhs.promote(), rhs.promote());
+        if lhs.size() != rhs.size() {
+            return if lhs.size() > rhs.size() { lhs } else { rhs };
+        }
+        if rhs.is_unsigned() {
+            rhs
+        } else {
+            lhs
+        }
+    }
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let s = match self {
+            Type::Void => "void",
+            Type::Char => "char",
+            Type::Short => "short",
+            Type::Int => "int",
+            Type::Long => "long",
+            Type::UChar => "unsigned char",
+            Type::UShort => "unsigned short",
+            Type::UInt => "unsigned int",
+            Type::ULong => "unsigned long",
+            Type::Ptr(base) => return write!(f, "{} *", base),
+            Type::Array(base, len) => return write!(f, "{}[{}]", base, len),
+            Type::Func(ret) => return write!(f, "{} ()", ret),
+            Type::Struct(record) => {
+                let data = record.0.borrow();
+                let kind = if data.is_union { "union" } else { "struct" };
+                return match data.tag {
+                    Some(tag) => write!(f, "{} {}", kind, tag),
+                    None => write!(f, "{} (anonymous)", kind),
+                };
+            },
+        };
+        write!(f, "{}", s)
+    }
+}
@@ -3,7 +3,7 @@ assert() {
     expected="$1"
     input="$2"
 
-    cargo run "$input"
+    cargo run -q "^-f" "$input" > /dev/null || exit 1
     cc -o tmp tmp.s
     ./tmp
     actual="$?"
@@ -16,54 +16,371 @@ assert() {
     fi
 }
 
-assert 0 "0"
-assert 42 "42;"
+# コンパイルが失敗し、エラー出力に期待する文字列が含まれることを確かめる
+assert_error() {
+    expected="$1"
+    input="$2"
+
+    actual=$(cargo run -q "^-f" "$input" 2>&1 > /dev/null)
+    if [ "$?" = "0" ]; then
+      echo "$input => error expected, but compiled"
+      exit 1
+    fi
+    if [[ "$actual" == *"$expected"* ]]; then
+      echo "$input => error"
+    else
+      echo "$input => $expected expected, but got"
+      echo "$actual"
+      exit 1
+    fi
+}
+
+assert 0 "int main() { return 0; }"
+assert 42 "int main() { return 42; }"
+
+assert 2 "int main() { return 1+1; }"
+assert 10 "int main() { return 5+2+3; }"
+assert 50 "int main() { return 10 + 25 - 5 + 20; }"
+
+assert 47 'int main() { return 5+6*7; }'
+assert 15 'int main() { return 5*(9-6); }'
+assert 4 'int main() { return (3+5)/2; }'
+
+assert 10 "int main() { return -10+20; }"
+assert 1 "int main() { return +5-4; }"
+
+assert 0 'int main() { return 0==1; }'
+assert 1 'int main() { return 42==42; }'
+assert 1 'int main() { return 0!=1; }'
+assert 0 'int main() { return 42!=42; }'
+
+assert 1 'int main() { return 0<1; }'
+assert 0 'int main() { return 1<1; }'
+assert 0 'int main() { return 2<1; }'
+assert 1 'int main() { return 0<=1; }'
+assert 1 'int main() { return 1<=1; }'
+assert 0 'int main() { return 2<=1; }'
+
+assert 1 'int main() { return 1>0; }'
+assert 0 'int main() { return 1>1; }'
+assert 0 'int main() { return 1>2; }'
+assert 1 'int main() { return 1>=0; }'
+assert 1 'int main() { return 1>=1; }'
+assert 0 'int main() { return 1>=2; }'
+
+assert 5 "int main() { int a=5; return a; }"
+assert 10 "int main() { int a=7; int b=3; return a+b; }"
+
+assert 1 "int main() { return 1; }"
+assert 1 "int main() { int return_x = 1; return return_x; }"
+assert 3 "int main() { int return_x = 1; int return_y = 2; return return_x+return_y; }"
+
+assert 10 "int main() { if (1<5) return 10; return 0; }"
+assert 20 "int main() { if (1>2) return 10; else return 20; }"
+assert 10 "int main() { if (1<2) if (1>3) return 20; else return 10; else return 30; }"
+
+assert 30 "int main() { if (1<2) { 10; 20; return 30; } return 0; }"
+
+assert 3 "int ret3() { return 3; } int main() { return ret3(); }"
+assert 7 "int add2(int a, int b) { return a+b; } int main() { return add2(3, 4); }"
+assert 35 "int sub6(int a, int b, int c, int d, int e, int f) { return a-b-c-d-e-f; } int main() { return sub6(100, 10, 20, 5, 15, 15); }"
+assert 55 "int fib(int n) { if (n<2) return n; return fib(n-1) + fib(n-2); } int main() { return fib(10); }"
+assert 0 'int main() { printf("hello %d\n", 42); return 0; }'
+
+assert 3 "int main() { int a=1; { int a=2; { int a=3; return a; } } }"
+assert 2 "int main() { int a=1; { int a=2; a; } { int b=5; } return a+1; }"
+assert 8 "int main() { int a=3, b, c=5; b=a+c; return b; }"
+assert 7 "int main() { char c=3; short s=2; long l=2; return c+s+l; }"
+assert 6 "long sq(long x) { return x*x; } int main(void) { long y=sq(2); return y+2; }"
+
+assert 3 "int main() { int x=3; return *&x; }"
+assert 3 "int main() { int x=3; int *y=&x; int **z=&y; return **z; }"
+assert 5 "int main() { int x=3; int *y=&x; *y=5; return x; }"
+assert 7 "int set(int *p) { *p=7; return 0; } int main() { int x=3; set(&x); return x; }"
+assert 4 "int main() { int x=3; int y=5; int *p=&y; int *q=p+4; return q-p; }"
+assert 1 "int main() { long x; long *p=&x; return (p+1)-p; }"
+assert 65 "int main() { char c; char *p=&c; *p=65; return c; }"
+
+assert 3 "int main() { int a[2]; *a=1; *(a+1)=2; int *p=a; return *p + *(p+1); }"
+assert 6 "int main() { int a[3]; a[0]=1; a[1]=2; a[2]=3; return a[0]+a[1]+a[2]; }"
+assert 5 "int main() { int a[2][3]; a[1][2]=5; int *p=a; return p[5]; }"
+assert 2 "int main() { int a[3]; 1[a]=2; return a[1]; }"
+assert 12 "int main() { int a[3]; return sizeof a; }"
+assert 24 "int main() { int a[2][3]; return sizeof(a); }"
+assert 12 "int main() { int a[2][3]; return sizeof a[1]; }"
+assert 8 "int main() { int a[2]; return sizeof(a + 1); }"
+assert 9 "int sum(int *a) { return a[0]+a[1]; } int main() { int a[2]; a[0]=4; a[1]=5; return sum(a); }"
+assert 98 'int main() { return "abc"[1]; }'
+
+assert 0 "int x; int main() { return x; }"
+assert 3 "int x; int main() { x=3; return x; }"
+assert 7 "int x=3, y=4; int main() { return x+y; }"
+assert 5 "int x[4]; int main() { x[3]=5; return x[3]; }"
+assert 2 "int x; int main() { int x=2; return x; }"
+assert 14 "long g = 2 * 3 + 8; int main() { return g; }"
+assert 98 'char s[4] = "abc"; int main() { return s[1]; }'
+assert 99 'char *p = "abc"; int main() { return p[2]; }'
+assert 98 'int main() { char s[4] = "abc"; return s[1]; }'
+assert 0 'int main() { char s[8] = "abc"; s[7] = 1; char t[8] = "xy"; return t[5]; }'
+assert 3 'int main() { char s[3] = "abc"; return sizeof(s); }'
+assert_error "<input>:1:7: error[E0100]: array is too large" 'int a[4611686018427387904]; int main() { return 0; }'
+assert_error "error[E0100]: array is too large" 'int main() { return sizeof(int[2305843009213693952][4]); }'
+assert_error "<input>:1:19: error[E0200]: stack frame is too large: 'char[3000000000]' does not fit" 'int main() { char a[3000000000]; int x = 5; a[0] = 1; return x; }'
+assert 3 'char g[3000000000]; int main() { g[2999999999] = 3; return g[2999999999]; }'
+assert_error "error[E0100]: array initializer must be a string literal" 'int main() { int a[3] = 5; return 0; }'
+assert_error "initializer-string for char array is too long" 'int main() { char s[2] = "abc"; return 0; }'
+assert 4 "static int n=3; static int inc() { n=n+1; return n; } int main() { return inc(); }"
+
+assert 3 "int main() { struct { int a; int b; } x; x.a=1; x.b=2; return x.a+x.b; }"
+assert 16 "int main() { struct { char a; long b; } x; return sizeof(x); }"
+assert 8 "int main() { union { char a; long b; int c; } x; return sizeof(x); }"
+assert 12 "int main() { struct { char a; int b; char c; } x; return sizeof x; }"
+assert 7 "struct p { int x; int y; }; int main() { struct p a; struct p *q=&a; q->y=7; return a.y; }"
+assert 5 "struct p { int x; int y; }; int main() { struct p a; struct p b; a.x=2; a.y=3; b=a; return b.x+b.y; }"
+assert_error "error[E0100]: assigning to 'struct S' from incompatible type 'int'" 'int main() { struct S { int a; } s; s = 1; return 0; }'
+assert_error "error[E0100]: assigning to 'int' from incompatible type 'struct S'" 'int main() { struct S { int a; } s; int x = s; return 0; }'
+assert_error "error[E0100]: assigning to 'struct S' from incompatible type 'struct T'" 'int main() { struct S { int a; } s; struct T { int a; } t; s = t; return 0; }'
+assert_error "error[E0200]: initializing 'struct S' is not supported for global variables" 'struct S { long a; long b; } s = 1; int main() { return 0; }'
+assert 3 "struct n { int v; struct n *next; }; int main() { struct n a; struct n b; a.next=&b; b.v=3; return a.next->v; }"
+assert 4 "int main() { union { int a; char b; } u; u.a=260; return u.b; }"
+assert 9 "int main() { struct { int a; struct { int b; }; } s; s.b=9; return s.b; }"
+assert 2 "struct t { int a; } g; int main() { g.a=2; { struct t { char c; } x; x.c=1; } return g.a; }"
+
+assert 0 "enum { A, B, C }; int main() { return A; }"
+assert 2 "enum { A, B, C }; int main() { return C; }"
+assert 6 "enum { A=5, B, C=-1 }; int main() { return B; }"
+assert 0 "enum { A=5, B, C=-1, D }; int main() { return D; }"
+assert 9 "enum { A=2*4+1 }; int main() { return A; }"
+assert 4 "int main() { enum e { X, Y=3, Z }; enum e v=Z; return v; }"
+assert 12 "enum { N=3 }; int main() { int a[N]; return sizeof a; }"
+assert 4 "enum t { P }; int main() { enum t x; return sizeof(x); }"
+assert 7 "enum { K=1 }; int main() { int K=7; return K; }"
+assert 1 "enum e { X, Y }; int main() { { enum e v=Y; return v; } }"
+assert 1 "int main() { enum e { X }; { enum e { Y, Z }; return Z; } }"
+assert 0 "enum { A = 2147483647, B = -1, C }; int main() { return C; }"
+assert 1 "enum { A = -2147483648 }; int main() { return A < 0; }"
+assert_error "<input>:1:12: error[E0100]: enumerator value is not representable in 'int'" 'enum { A = 9223372036854775807, B }; int main() { return 0; }'
+assert_error "<input>:1:12: error[E0100]: enumerator value is not representable in 'int'" 'enum { A = 4294967296 }; int main() { return A == 0; }'
+assert_error "<input>:1:24: error[E0100]: overflow in enumeration value" 'enum { A = 2147483647, B }; int main() { return 0; }'
+assert_error "<input>:1:19: error[E0101]: use of undeclared enum 'nosuch'" 'int main() { enum nosuch x; return 0; }'
+assert_error "error[E0101]: use of undeclared enum 'e'" 'int main() { { enum e { X }; } enum e v; return 0; }'
+assert_error "error[E0102]: redefinition of 'enum e'" 'enum e { X }; enum e { Y }; int main() { return 0; }'
+
+assert 3 "typedef int T; int main() { T x=3; return x; }"
+assert 8 "typedef long T; int main() { T x; return sizeof(x); }"
+assert 5 "typedef struct node Node; struct node { int v; Node *next; }; int main() { Node n; Node *p=&n; p->v=5; return n.v; }"
+assert 12 "typedef int A[3]; int main() { A a; return sizeof a; }"
+assert 4 "typedef int T; int main() { T *x; T y=4; x=&y; return *x; }"
+assert 6 "typedef int T; int main() { int T=3; return T * 2; }"
+assert 1 "typedef int T; int main() { { typedef char T; T c; return sizeof(c); } }"
+assert 2 "int main() { typedef int t; t a=2; return a; }"
+assert 3 "typedef int T; typedef int T; int main() { T a=3; return a; }"
+
+assert 4 "int main() { return sizeof(int); }"
+assert 8 "int main() { return sizeof(int *); }"
+assert 1 "int main() { return sizeof(char); }"
+assert 24 "int main() { return sizeof(int[3][2]); }"
+assert 16 "struct s { char c; long l; }; int main() { return sizeof(struct s); }"
+assert 8 "struct s { char c; long l; }; int main() { return _Alignof(struct s); }"
+assert 2 "int main() { return _Alignof(short); }"
+assert 4 "typedef int T[2]; int main() { return _Alignof(T); }"
+assert 0 "int main() { int x=0; sizeof(x=5); return x; }"
+assert 8 "int main() { return sizeof(sizeof(int)); }"
+
+assert 1 "int main() { return (char)257; }"
+assert 255 "int main() { return (unsigned char)-1; }"
+assert 0 "int main() { return -1 < 0u; }"
+assert 1 "int main() { unsigned int a=-1; return a > 0; }"
+assert 1 "int main() { char c=200; return c < 0; }"
+assert 1 "int main() { int a=2147483647; return a+1 < 0; }"
+assert 1 "int main() { unsigned a=0; return a-1 == 4294967295; }"
+assert 1 "int main() { long a=4294967296; return (int)a == 0; }"
+assert 3 "int main() { return -7/2 + 6; }"
+assert 1 "int main() { return (unsigned)-7/2 == 2147483644; }"
+assert 1 "int main() { short s=65535; return s == -1; }"
+assert 1 "int main() { unsigned short s=65535; return s == 65535; }"
+# 定数式も符号なしなら符号なしで計算する
+assert 0 "int g = -1 < 0u; int main() { return g; }"
+assert 1 "int g = 1 <= -1u; int main() { return g; }"
+assert 0 "int g = -1u < 1; int main() { return g; }"
+assert 1 "unsigned long g = -1ul / 2; int main() { return g == 9223372036854775807; }"
+assert 1 "unsigned g = (unsigned)-7 / 2; int main() { return g == 2147483644; }"
+assert 5 "unsigned g = -1u % 10; int main() { return g; }"
+assert 1 "enum { N = (-1 < 0u) + 1 }; int main() { return N; }"
+assert 4 "enum { M = -1ul / 2 > 0 ? 4 : 5 }; int main() { return M; }"
+assert 1 "int main() { signed char c=-1; long l=c; return l == -1; }"
+assert 8 "int main() { return sizeof(unsigned long int); }"
+assert 4 "char f() { return 260; } int main() { return f(); }"
+assert 1 "int g = (char)300 == 44; int main() { return g; }"
+
+assert 0 "int main() { return 1 && 0; }"
+assert 1 "int main() { return 0 || 5; }"
+assert 0 "int x; int f() { x=1; return 1; } int main() { 0 && f(); return x; }"
+assert 0 "int x; int f() { x=1; return 1; } int main() { 1 || f(); return x; }"
+assert 1 "int x; int f() { x=1; return 1; } int main() { 1 && f(); return x; }"
+assert 1 "int main() { return 1 || 0 && 0; }"
+assert 1 "int main() { int *p=0; return !p; }"
+assert 3 "int main() { return !!5 + 2; }"
+
+assert 2 "int main() { return 17 % 5; }"
+assert 1 "int main() { return -7 % 2 == -1; }"
+assert 1 "int main() { return (unsigned)-1 % 10 == 5; }"
+assert 2 "int main() { return 6 & 3; }"
+assert 7 "int main() { return 6 | 3; }"
+assert 5 "int main() { return 6 ^ 3; }"
+assert 1 "int main() { return ~0 == -1; }"
+assert 16 "int main() { return 1 << 4; }"
+assert 1 "int main() { return -16 >> 2 == -4; }"
+assert 1 "int main() { return (unsigned)-16 >> 28 == 15; }"
+assert 3 "int main() { return 1 | 2 & 3; }"
+assert 1 "int main() { return 2 + 3 << 1 == 10; }"
+assert 1 "int main() { unsigned h=2166136261; h = h ^ 97; h = h * 16777619; return h == 3826002220; }"
+
+assert 7 "int main() { int a=5; a += 2; return a; }"
+assert 3 "int main() { int a=5; a -= 2; return a; }"
+assert 10 "int main() { int a=5; a *= 2; return a; }"
+assert 1 "int main() { int a=5; a %= 2; return a; }"
+assert 7 "int main() { int a=6; a |= 5; return a; }"
+assert 24 "int main() { int a=6; a <<= 2; return a; }"
+assert 8 "int main() { int a=5; return a += 3; }"
+assert 5 "int main() { int a=5; return a++; }"
+assert 6 "int main() { int a=5; return ++a; }"
+assert 4 "int main() { int a=5; return --a; }"
+assert 3 "int main() { int x[3]; x[0]=1; x[1]=2; x[2]=3; int *p=x; p++; p += 1; return *p; }"
+assert 1 "int main() { int x[3]; x[0]=1; x[1]=2; int *p=x; return *p++; }"
+assert 3 "struct S { int a; int b; }; int main() { struct S s; struct S *p=&s; p->a=2; p->a++; return p->a; }"
+assert 15 "int n; int v; int *f() { n = n + 1; return &v; } int main() { *f() += 5; return n*10 + v; }"
+assert 0 "int main() { unsigned char c=255; c++; return c; }"
+assert 255 "int main() { unsigned char c=255; return c++; }"
+
+assert 2 "int main() { return 1 ? 2 : 3; }"
+assert 3 "int main() { return 0 ? 2 : 3; }"
+assert 5 "int main() { int a=0; return a ? 1 : a == 0 ? 5 : 6; }"
+assert 0 "int x; int f() { x=1; return 1; } int main() { 1 ? 2 : f(); return x; }"
+assert 1 "int main() { return (0 ? 1 : -1u) > 0; }"
+assert 97 'int main() { return (1 ? "a" : "bc")[0]; }'
+assert 99 'int main() { return (0 ? "a" : "bc")[1]; }'
+assert 98 'int main() { char *p = "xbz"; int c=0; return (c ? "abc" : p)[1]; }'
+assert 3 'int main() { int a[2]; int b[3]; a[1]=2; b[1]=3; int c=0; return (c ? a : b)[1]; }'
+assert 8 'int main() { int a[4]; return sizeof(1 ? a : a); }'
+assert 3 "int main() { int a, b; a = 1, b = 2; return a + b; }"
+assert 4 "int main() { return (1, 2, 4); }"
+assert 8 "int main() { char x[1 ? 8 : 2]; return sizeof(x); }"
+
+assert 55 "int main() { int s=0; for (int i=1; i<=10; i++) s += i; return s; }"
+assert 10 "int main() { int i=0; for (;;) { i++; if (i == 10) return i; } }"
+assert 45 "int main() { int s=0; for (int i=0, j=0; i<10; i++, j += 2) s += i; return s; }"
+assert 3 "int main() { int i=3; for (int i=0; i<10; i++) ; return i; }"
+assert 0 "int main() { int i=0; while (0) i=5; return i; }"
+assert 21 "int fib(int n) { int a=0, b=1; while (n--) { int t=a+b; a=b; b=t; } return a; } int main() { return fib(8); }"
+
+assert 6 "int main() { int a=0; while (a<6) a = a + 1; return a; }"
+
+assert 6 "int main() { int a; for (a=0;a<6;a=a+1) a; return a; }"
+
+assert_error $'<input>:1:23: error[E0100]: expected \';\'\n 1 | int main() { return 5 }\n   |                       ^' 'int main() { return 5 }'
+assert_error $'<input>:1:21: error[E0101]: use of undeclared identifier \'foo\'\n 1 | int main() { return foo; }\n   |                     ^~~' 'int main() { return foo; }'
 
-assert 2 "1+1;"
-assert 10 "5+2+3;"
-assert 50 "10 + 25 - 5 + 20;"
+# 記号は最長一致で区切る
+assert 8 'int main() { int a=1; a<<=3; return a; }'
+assert 2 'int main() { int a=16; a>>=3; return a; }'
+assert 5 'int main() { int a=2, b=3; return a+++b; }'
+assert 1 'int main() { int a=2, b=3; return a---b+2; }'
+assert 1 'int main() { int a=3; return a&&a&1; }'
+assert 7 'struct S { int x; }; int main() { struct S s; struct S *p=&s; p->x=7; return p->x; }'
+assert_error $' 1 | int main() { return ...; }\n   |                     ^~~' 'int main() { return ...; }'
+assert_error $' 1 | int main() { return 1 ## 2; }\n   |                       ^~' 'int main() { return 1 ## 2; }'
+assert_error $'<input>:1:26: error[E0100]: expected expression\n 1 | int main() { int a; a <<== 1; }\n   |                          ^' 'int main() { int a; a <<== 1; }'
 
-assert 47 '5+6*7;'
-assert 15 '5*(9-6);'
-assert 4 '(3+5)/2;'
+# 整数リテラル
+assert 31 'int main() { return 0x1f; }'
+assert 31 'int main() { return 0X1F; }'
+assert 15 'int main() { return 017; }'
+assert 5 'int main() { return 0b101; }'
+assert 4 'int main() { return sizeof(2147483647); }'
+assert 8 'int main() { return sizeof(2147483648); }'
+assert 4 'int main() { return sizeof(0xffffffff); }'
+assert 8 'int main() { return sizeof(0x100000000); }'
+assert 4 'int main() { return sizeof(1u); }'
+assert 8 'int main() { return sizeof(1l); }'
+assert 8 'int main() { return sizeof(1ll); }'
+assert 8 'int main() { return sizeof(1ULL); }'
+assert 1 'int main() { return 0xffffffff > 0; }'
+assert 1 'int main() { return 1u - 2 > 0; }'
+assert 0 'int main() { return -1 < 0xffffffff; }'
+assert_error "error[E0004]: integer literal is too large to be represented in any integer type" 'int main() { return 18446744073709551616; }'
+assert_error "error[E0004]: invalid digit '8' in octal constant" 'int main() { return 08; }'
+assert_error "error[E0004]: invalid suffix 'lL' on integer constant" 'int main() { return 1lL; }'
 
-assert 10 "^-10+20;"
-assert 1 "+5-4;"
+# エスケープシーケンスと文字列の連結
+assert 65 'int main() { return "\x41"[0]; }'
+assert 65 'int main() { return "\101"[0]; }'
+assert 39 $'int main() { return \'\\\'\'; }'
+assert 111 $'int main() { return \'\\n\' + \'\\t\' + \'\\\\\'; }'
+assert 5 'int main() { return sizeof("ab" "cd"); }'
+assert 100 'int main() { return ("ab" "cd")[3]; }'
+assert_error "error[E0003]: \\x used with no following hex digits" 'int main() { return "\xg"[0]; }'
+assert_error 'error[E0002]: missing terminating " character' 'int main() { return "abc; }'
 
-assert 0 '0==1;'
-assert 1 '42==42;'
-assert 1 '0!=1;'
-assert 0 '42!=42;'
+# コメント
+assert 3 $'int main() { // return 1;\n return 3; }'
+assert 4 'int main() { /* return 1; */ return 4; }'
+assert 5 $'int main() {\n /* a\n b */ return /**/ 5; }'
+assert_error "<input>:3:11: error[E0101]: use of undeclared identifier 'x'" $'int main() { /* a\nb\n*/ return x; }'
+assert_error "<input>:2:12: error[E0100]: expected ';'" $'int main() { // c\n  return 1 }'
+assert_error "<input>:2:3: error[E0002]: unterminated comment" $'int main() {\n  /* return 0; }'
 
-assert 1 '0<1;'
-assert 0 '1<1;'
-assert 0 '2<1;'
-assert 1 '0<=1;'
-assert 1 '1<=1;'
-assert 0 '2<=1;'
+assert 3 'int x; void f() { x = 3; return; x = 4; } int main() { f(); return x; }'
+assert 2 'int x; void f(int a) { if (a) return; x = 2; } int main() { f(0); f(1); return x; }'
 
-assert 1 '1>0;'
-assert 0 '1>1;'
-assert 0 '1>2;'
-assert 1 '1>=0;'
-assert 1 '1>=1;'
-assert 0 '1>=2;'
+# レジスタが足りなければスタックに追い出す
+assert 21 'int f(int a,int b,int c,int d,int e,int g){ return a+b*2+c*3+d*4+e*5+g*6; } int main(){ int x[6]; x[0]=1;x[1]=1;x[2]=1;x[3]=1;x[4]=1;x[5]=1; return f(x[0],x[1],x[2],x[3],x[4],x[5]); }'
+assert 21 'int f(int a,int b,int c,int d,int e,int g){ return a+b+c+d+e+g; } int main(){ return f(1,2,3,4,5,f(1,1,1,1,1,1)); }'
+assert 45 'int main(){ return 1+(2+(3+(4+(5+(6+(7+(8+9))))))); }'
+assert 36 'int main(){ int a=3; return 1+(2+(3+(4+(5+(6+(7+(a ? 8 : 9))))))); }'
+assert 28 'int f(int a,int b,int c,int d,int e,int g){ return a-b+c-d+e-g; } int main(){ int a=1; return 1+(2+(3+(4+(5+(6+(7+(f(1,2,f(a,2,3,4,5,6),4,a?5:6,6)+9))))))); }'
 
-assert 5 "a=5;"
-assert 10 "a=7;b=3;a+b;"
+assert_error "<input>:1:54: error[E0200]: too many parameters: at most 6 are supported" 'int f(int a, int b, int c, int d, int e, int f2, int g) { return g; } int main() { return 0; }'
 
-assert 1 "return 1;"
-assert 1 "return_x = 1;"
-assert 3 "return_x = 1; return_y = 2; return return_x+return_y;"
+# ファイル全体のスコープで同じ名前は1回しか定義できない
+assert_error "<input>:1:17: error[E0102]: redefinition of 'A'" 'enum { A }; int A = 5; int main() { return A; }'
+assert_error "<input>:1:20: error[E0102]: redefinition of 'T'" 'typedef int T; int T; int main() { return 0; }'
+assert_error "<input>:1:12: error[E0102]: redefinition of 'f'" 'int f; int f() { return 1; } int main() { return 0; }'
+assert_error "<input>:1:27: error[E0102]: redefinition of 'f'" 'int f() { return 1; } int f() { return 2; } int main() { return f(); }'
+assert_error "<input>:1:30: error[E0102]: redefinition of 'main'" 'int main() { return 1; } int main() { return 2; }'
+assert_error "<input>:1:15: error[E0102]: redefinition of 'x'" 'int x; enum { x }; int main() { return 0; }'
+assert 2 'int x; int main() { int x = 2; enum { f }; return x + f; } int f() { return 0; }'
 
-assert 10 "if (1<5) 10;"
-assert 20 "if (1>2) 10; else 20;"
-assert 10 "if (1<2) if (1>3) 20; else 10; else 30;"
+# 1回のコンパイルで複数のエラーを報告する
+assert_error "<input>:1:25: error[E0100]: expected expression" $'int main() { int x = 1 +; return 2 }\nint f() { return ; }'
+assert_error "<input>:1:36: error[E0100]: expected ';'" $'int main() { int x = 1 +; return 2 }\nint f() { return ; }'
+assert_error "<input>:2:18: error[E0100]: expected expression" $'int main() { int x = 1 +; return 2 }\nint f() { return ; }'
 
-assert 6 "a=0; while (a<6) a = a + 1; a;"
+# ファイルから読んだソースもコンパイルできる
+cargo run -q test/1.c > /dev/null || exit 1
+cc -o tmp tmp.s
+./tmp
+if [ "$?" != "6" ]; then
+  echo "test/1.c => 6 expected"
+  exit 1
+fi
 
-assert 6 "for (a=0;a<6;a=a+1) a;"
+# 読めない引数はパニックせずにエラーで終わる
+for arg in "" "é"; do
+  err=$(cargo run -q "$arg" 2>&1 > /dev/null)
+  if [ "$?" = "0" ] || echo "$err" | grep -q "panicked"; then
+    echo "'$arg' => clean error expected, but got"
+    echo "$err"
+    exit 1
+  fi
+done
 
-assert 30 "if (1<2) { 10;20; return 30; }"
+# -l で構造体の配置を表示する
+layout=$(cargo run -q "^-l" test/struct.c)
+if ! echo "$layout" | grep -q "sizeof=16, align=8"; then
+  echo "test/struct.c => sizeof=16, align=8 expected, but got"
+  echo "$layout"
+  exit 1
+fi
 
-echo OK
\ No newline at end of file
+echo OK
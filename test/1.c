@@ -1,4 +1,4 @@
-main () {
+int main() {
     if (5>=6) {
         return 5;
     }
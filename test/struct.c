@@ -0,0 +1,13 @@
+struct point {
+    char tag;
+    int x;
+    long y;
+};
+
+int main() {
+    struct point p;
+    p.tag = 1;
+    p.x = 2;
+    p.y = 3;
+    return p.tag + p.x + p.y;
+}